@@ -0,0 +1,157 @@
+use std::sync::{Mutex, RwLock};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+
+pub const AVATAR_CHANGE: &str = "/avatar/change";
+pub const PARAMETERS_PREFIX: &str = "/avatar/parameters/";
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub enum ParameterType {
+    Bool,
+    Int,
+    Float,
+}
+
+impl ParameterType {
+    /// Parses an OSC type tag such as `f`, bools are either `T` or `F`
+    #[must_use]
+    pub fn from_type_tag(tag: &str) -> Option<Self> {
+        match tag {
+            "T" | "F" => Some(Self::Bool),
+            "i" => Some(Self::Int),
+            "f" => Some(Self::Float),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Parameter {
+    /// The address after `/avatar/parameters/`, such as `VRCOSC/Clock/Hours`
+    pub name: String,
+    pub kind: ParameterType,
+}
+
+impl Parameter {
+    #[must_use]
+    pub fn address(&self) -> String {
+        format!("{PARAMETERS_PREFIX}{}", self.name)
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Avatar {
+    /// `None` until VRChat reports the current avatar
+    pub id:         Option<String>,
+    pub parameters: Vec<Parameter>,
+}
+
+impl Avatar {
+    /// Returns `None` if the avatar doesn't have the parameter
+    #[must_use]
+    pub fn parameter(&self, name: &str) -> Option<&Parameter> {
+        self.parameters
+            .iter()
+            .find(|parameter| parameter.name == name)
+    }
+
+    /// Whether the parameters are known, plugins shouldn't skip sending until they are
+    #[must_use]
+    pub const fn is_known(&self) -> bool {
+        self.id.is_some()
+    }
+}
+
+static CURRENT: RwLock<Avatar> = RwLock::new(Avatar {
+    id:         None,
+    parameters: Vec::new(),
+});
+static CHANGED: Notify = Notify::const_new();
+static CHANGED_ID: Mutex<Option<String>> = Mutex::new(None);
+
+/// The loader's view of the current avatar, plugins should use [`crate::host::avatar`]
+///
+/// # Panics
+///
+/// Will panic if the avatar lock was poisoned
+#[must_use]
+pub fn current() -> Avatar {
+    CURRENT.read().expect("Failed to read the avatar").clone()
+}
+
+/// Borrows the current avatar without cloning its parameters
+///
+/// # Panics
+///
+/// Will panic if the avatar lock was poisoned
+pub fn with_current<T>(f: impl FnOnce(&Avatar) -> T) -> T {
+    f(&CURRENT.read().expect("Failed to read the avatar"))
+}
+
+/// # Panics
+///
+/// Will panic if the avatar lock was poisoned
+pub fn set_current(avatar: Avatar) {
+    *CURRENT.write().expect("Failed to write the avatar") = avatar;
+}
+
+/// Called by the router when VRChat reports an avatar change
+///
+/// The previous avatar is forgotten until the new one is discovered, so nothing is validated against it
+///
+/// # Panics
+///
+/// Will panic if the avatar or avatar id lock was poisoned
+pub fn notify_changed(id: Option<String>) {
+    let mut current = CURRENT.write().expect("Failed to write the avatar");
+    if current.id != id {
+        *current = Avatar::default();
+    }
+    drop(current);
+
+    *CHANGED_ID.lock().expect("Failed to lock the avatar id") = id;
+    CHANGED.notify_one(); // Kept until the tracker is waiting again
+}
+
+/// Waits until VRChat reports an avatar change and returns the new avatar id
+///
+/// # Panics
+///
+/// Will panic if the avatar id lock was poisoned
+pub async fn changed() -> Option<String> {
+    CHANGED.notified().await;
+    CHANGED_ID
+        .lock()
+        .expect("Failed to lock the avatar id")
+        .clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn avatar(id: &str) -> Avatar {
+        Avatar {
+            id:         Some(id.into()),
+            parameters: vec![Parameter {
+                name: "VRCOSC/Clock/Hours".into(),
+                kind: ParameterType::Float,
+            }],
+        }
+    }
+
+    #[test]
+    fn forgets_the_previous_avatar_until_the_new_one_is_known() {
+        set_current(avatar("avtr_previous"));
+        notify_changed(Some("avtr_previous".into()));
+        assert!(current().parameter("VRCOSC/Clock/Hours").is_some());
+
+        notify_changed(Some("avtr_next".into()));
+        assert!(!current().is_known());
+        assert!(current().parameters.is_empty());
+
+        set_current(avatar("avtr_next"));
+        assert!(current().is_known());
+    }
+}
@@ -0,0 +1,135 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+use crate::avatar::{self, Avatar, Parameter, ParameterType};
+
+const VRCHAT_APP_ID: &str = "438100";
+const OSC_DIR: &str = "AppData/LocalLow/VRChat/VRChat/OSC";
+
+/// One of VRChat's `OSC/usr_*/Avatars/avtr_*.json` files
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AvatarConfig {
+    pub id:         String,
+    pub name:       String,
+    pub parameters: Vec<ParameterConfig>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ParameterConfig {
+    pub name:   String,
+    /// Where VRChat receives the parameter, `None` for read-only parameters such as `VelocityZ`
+    pub input:  Option<Endpoint>,
+    /// Where VRChat sends the parameter
+    pub output: Option<Endpoint>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Endpoint {
+    pub address: String,
+    #[serde(rename = "type")]
+    pub kind:    ParameterType,
+}
+
+impl From<AvatarConfig> for Avatar {
+    fn from(config: AvatarConfig) -> Self {
+        let parameters = config
+            .parameters
+            .into_iter()
+            .filter_map(|parameter| {
+                let endpoint = parameter.input.or(parameter.output)?;
+                Some(Parameter {
+                    name: parameter.name,
+                    kind: endpoint.kind,
+                })
+            })
+            .collect();
+
+        Self {
+            id: Some(config.id),
+            parameters,
+        }
+    }
+}
+
+/// VRChat's OSC config directory, the override is used as is when set
+///
+/// Looks in `LocalLow` on Windows and the Proton prefix on Linux
+#[must_use]
+pub fn osc_dir(path_override: Option<&str>) -> Option<PathBuf> {
+    if let Some(path) = path_override {
+        return Some(PathBuf::from(path));
+    }
+
+    let candidates = if cfg!(windows) {
+        let profile = std::env::var("USERPROFILE").ok()?;
+        vec![Path::new(&profile).join(OSC_DIR)]
+    } else {
+        let home = std::env::var("HOME").ok()?;
+        let user = format!("pfx/drive_c/users/steamuser/{OSC_DIR}");
+        [".steam/steam", ".local/share/Steam"]
+            .iter()
+            .map(|steam| {
+                Path::new(&home)
+                    .join(steam)
+                    .join("steamapps/compatdata")
+                    .join(VRCHAT_APP_ID)
+                    .join(&user)
+            })
+            .collect()
+    };
+
+    candidates.into_iter().find(|path| path.is_dir())
+}
+
+/// # Errors
+///
+/// Will return `Err` if the file couldn't be read or isn't an avatar config
+pub fn load(path: &Path) -> Result<AvatarConfig> {
+    let json = std::fs::read_to_string(path)?;
+
+    // VRChat writes these with a byte order mark
+    let json = json.trim_start_matches('\u{feff}');
+
+    serde_json::from_str(json).with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+/// Finds the config for the avatar id across every user's `Avatars` directory
+///
+/// # Errors
+///
+/// Will return `Err` if the avatar has no config or it couldn't be loaded
+pub fn find(osc_dir: &Path, id: &str) -> Result<AvatarConfig> {
+    let filename = format!("{id}.json");
+    let path = WalkDir::new(osc_dir)
+        .min_depth(3)
+        .max_depth(3)
+        .into_iter()
+        .filter_map(Result::ok)
+        .map(walkdir::DirEntry::into_path)
+        .find(|path| path.ends_with(Path::new("Avatars").join(&filename)))
+        .with_context(|| format!("{id} has no OSC config in {}", osc_dir.display()))?;
+
+    load(&path)
+}
+
+/// Keeps [`avatar::current`] up to date from the config files whenever the avatar changes
+pub fn track_avatar(osc_dir: PathBuf) {
+    tokio::spawn(async move {
+        loop {
+            let Some(id) = avatar::changed().await else {
+                continue;
+            };
+
+            match find(&osc_dir, &id) {
+                Ok(config) => avatar::set_current(config.into()),
+                Err(error) => {
+                    tracing::error!("{error:#}");
+                    avatar::set_current(Avatar::default());
+                }
+            }
+        }
+    });
+}
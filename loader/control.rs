@@ -0,0 +1,458 @@
+//! A JSON-RPC 2.0 endpoint on a Unix socket, or a named pipe on Windows, to drive the running loader
+//!
+//! Requests and responses are one JSON object per line, see [`call`] for the client side
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::UdpSocket,
+    path::PathBuf,
+    sync::Arc,
+};
+
+use anyhow::{bail, Context, Result};
+use rosc::{OscMessage, OscPacket, OscType};
+use serde::{de::DeserializeOwned, Deserialize, Deserializer};
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt};
+
+use crate::{
+    avatar::{self, PARAMETERS_PREFIX},
+    host,
+    parameters,
+    reload,
+    router::{self, Router, CHATBOX_INPUT},
+    Config,
+    RunningPlugins,
+};
+
+/// Who rate limits and session recordings see sending what comes through the control socket
+const SOURCE: &str = "Control";
+
+/// `control_socket`, or `vrc-osc.sock` in the runtime or temporary directory, `\\.\pipe\vrc-osc` on Windows
+#[must_use]
+pub fn path(config: &Config) -> String {
+    config.control_socket.clone().unwrap_or_else(|| {
+        if cfg!(windows) {
+            r"\\.\pipe\vrc-osc".into()
+        } else {
+            let dir =
+                std::env::var_os("XDG_RUNTIME_DIR").map_or_else(std::env::temp_dir, PathBuf::from);
+
+            dir.join("vrc-osc.sock").to_string_lossy().into_owned()
+        }
+    })
+}
+
+/// A JSON-RPC error, with the codes from the spec
+struct RpcError {
+    code:    i64,
+    message: String,
+}
+
+impl RpcError {
+    const PARSE_ERROR: i64 = -32700;
+    const INVALID_REQUEST: i64 = -32600;
+    const METHOD_NOT_FOUND: i64 = -32601;
+    const INVALID_PARAMS: i64 = -32602;
+    /// The loader failed to do what was asked
+    const FAILED: i64 = -32000;
+
+    const fn new(code: i64, message: String) -> Self {
+        Self { code, message }
+    }
+}
+
+impl From<anyhow::Error> for RpcError {
+    fn from(error: anyhow::Error) -> Self {
+        Self::new(Self::FAILED, format!("{error:#}"))
+    }
+}
+
+#[derive(Deserialize)]
+struct Request {
+    /// Notifications without an id get no response, a `null` id still gets one
+    #[serde(default, deserialize_with = "present")]
+    id:     Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+/// Tells a `null` member apart from a missing one, which `Option` alone doesn't
+fn present<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Value>, D::Error> {
+    Value::deserialize(deserializer).map(Some)
+}
+
+#[derive(Deserialize)]
+struct PluginParams {
+    /// The plugin's name or filename
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct ChatParams {
+    text:   String,
+    /// Plays VRChat's notification sound
+    #[serde(default)]
+    notify: bool,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct GetParams {
+    /// Every parameter when unset
+    name: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SetParams {
+    name:  String,
+    value: Value,
+}
+
+fn params<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    // Methods without required params can be called without any
+    let params = if params.is_null() { json!({}) } else { params };
+
+    serde_json::from_value(params)
+        .map_err(|error| RpcError::new(RpcError::INVALID_PARAMS, error.to_string()))
+}
+
+fn response(id: &Value, result: Result<Value, RpcError>) -> Value {
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(error) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": error.code, "message": error.message },
+        }),
+    }
+}
+
+/// Parameters are bools, ints or floats, numbers with a fraction are floats
+fn parameter_arg(value: &Value) -> Result<OscType> {
+    match value {
+        Value::Bool(bool) => Ok(OscType::Bool(*bool)),
+        Value::Number(number) => match number.as_i64().map(i32::try_from) {
+            Some(Ok(int)) => Ok(OscType::Int(int)),
+            Some(Err(_)) => bail!("{number} is too big for a parameter"),
+            #[allow(clippy::cast_possible_truncation)]
+            None => Ok(OscType::Float(number.as_f64().unwrap_or_default() as f32)),
+        },
+        _ => bail!("Parameters are bools, ints or floats, not {value}"),
+    }
+}
+
+/// What the control socket reads from and changes in the running loader
+pub struct Control {
+    pub router:        Arc<Router>,
+    pub plugins:       RunningPlugins,
+    pub loader_socket: Arc<UdpSocket>,
+    pub config:        Config,
+}
+
+impl Control {
+    /// Listens on the socket or named pipe from the loader's runtime
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if another loader is listening on it or it couldn't be created
+    pub fn serve(self, path: &str) -> Result<()> {
+        let control = Arc::new(self);
+
+        #[cfg(unix)]
+        {
+            let listener = bind(path)?;
+            tokio::spawn(async move {
+                loop {
+                    match listener.accept().await {
+                        Ok((stream, _addr)) => control.clone().spawn_session(stream),
+                        Err(error) => tracing::error!("Control: {error}"),
+                    }
+                }
+            });
+        }
+
+        #[cfg(windows)]
+        {
+            use tokio::net::windows::named_pipe::ServerOptions;
+
+            let mut server = ServerOptions::new()
+                .first_pipe_instance(true)
+                .create(path)
+                .with_context(|| format!("Failed to create {path}, is another loader running?"))?;
+
+            let path = path.to_owned();
+            tokio::spawn(async move {
+                loop {
+                    if let Err(error) = server.connect().await {
+                        tracing::error!("Control: {error}");
+                        continue;
+                    }
+
+                    // The connected instance serves its client, the next client needs a new one
+                    let client = server;
+                    server = match ServerOptions::new().create(&path) {
+                        Ok(server) => server,
+                        Err(error) => {
+                            tracing::error!("Control: {error}");
+                            break;
+                        }
+                    };
+
+                    control.clone().spawn_session(client);
+                }
+            });
+        }
+
+        Ok(())
+    }
+
+    fn spawn_session(self: Arc<Self>, stream: impl AsyncRead + AsyncWrite + Send + 'static) {
+        tokio::spawn(async move {
+            if let Err(error) = self.session(stream).await {
+                tracing::warn!("Control: {error}");
+            }
+        });
+    }
+
+    /// Answers requests until the client disconnects
+    async fn session(&self, stream: impl AsyncRead + AsyncWrite) -> Result<()> {
+        let (reader, mut writer) = tokio::io::split(stream);
+        let mut lines = tokio::io::BufReader::new(reader).lines();
+        while let Some(line) = lines.next_line().await? {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            if let Some(response) = self.handle(&line).await {
+                writer.write_all(format!("{response}\n").as_bytes()).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn handle(&self, line: &str) -> Option<Value> {
+        let request = match serde_json::from_str::<Request>(line) {
+            Ok(request) => request,
+            Err(error) => {
+                let code = if error.is_data() {
+                    RpcError::INVALID_REQUEST
+                } else {
+                    RpcError::PARSE_ERROR
+                };
+
+                return Some(response(
+                    &Value::Null,
+                    Err(RpcError::new(code, error.to_string())),
+                ));
+            }
+        };
+
+        let result = self.call(&request.method, request.params).await;
+
+        Some(response(&request.id?, result))
+    }
+
+    async fn call(&self, method: &str, params: Value) -> Result<Value, RpcError> {
+        match method {
+            "status" => Ok(self.status()),
+            "plugins.enable" => Ok(self
+                .enable(&self::params::<PluginParams>(params)?.name)
+                .await?),
+            "plugins.disable" => Ok(self
+                .disable(&self::params::<PluginParams>(params)?.name)
+                .await?),
+            "chatbox.send" => Ok(self.chat(self::params(params)?)?),
+            "parameters.get" => Ok(Self::get(self::params(params)?)),
+            "parameters.set" => Ok(self.set(&self::params(params)?)?),
+            _ => Err(RpcError::new(
+                RpcError::METHOD_NOT_FOUND,
+                format!("Unknown method {method}"),
+            )),
+        }
+    }
+
+    fn status(&self) -> Value {
+        let plugins = self.plugins.read().expect("Failed to read plugins").clone();
+        let plugins = plugins
+            .iter()
+            .map(|running| running.describe())
+            .collect::<Vec<_>>();
+
+        json!({
+            "plugins": plugins,
+            "avatar": avatar::current(),
+            "chatbox": router::chatbox(),
+        })
+    }
+
+    /// Starts a plugin next to the executable, it stays enabled once the loader saves its config on shutdown
+    async fn enable(&self, name: &str) -> Result<Value> {
+        let filename = crate::get_plugins()?
+            .into_iter()
+            .find(|plugin| {
+                plugin.filename == name || plugin.metadata.name.eq_ignore_ascii_case(name)
+            })
+            .map(|plugin| plugin.filename)
+            .with_context(|| format!("There's no {name} plugin next to the executable"))?;
+
+        if self.running(&filename).is_none() {
+            tracing::info!("Loading {filename}");
+            reload::reload(&filename, &self.plugins, &self.loader_socket, &self.config).await?;
+        }
+
+        host::set_enabled(&filename, true);
+
+        Ok(json!({ "filename": filename }))
+    }
+
+    /// Unloads a running plugin, it stays disabled once the loader saves its config on shutdown
+    async fn disable(&self, name: &str) -> Result<Value> {
+        let Some(filename) = self.running(name) else {
+            bail!("The {name} plugin isn't running");
+        };
+
+        tracing::info!("Unloading {filename}");
+        reload::unload(&filename, &self.plugins, &self.loader_socket).await?;
+        host::set_enabled(&filename, false);
+
+        Ok(json!({ "filename": filename }))
+    }
+
+    /// The filename of the running plugin with this name or filename
+    fn running(&self, name: &str) -> Option<String> {
+        self.plugins
+            .read()
+            .expect("Failed to read plugins")
+            .iter()
+            .find(|running| {
+                running.plugin.filename == name
+                    || running.plugin.metadata.name.eq_ignore_ascii_case(name)
+            })
+            .map(|running| running.plugin.filename.clone())
+    }
+
+    fn chat(&self, params: ChatParams) -> Result<Value> {
+        let packet = OscPacket::Message(OscMessage {
+            addr: CHATBOX_INPUT.into(),
+            args: vec![
+                OscType::String(params.text),
+                OscType::Bool(true), // Send it now instead of opening the keyboard
+                OscType::Bool(params.notify),
+            ],
+        });
+
+        self.router.send(packet, SOURCE)?;
+
+        Ok(Value::Null)
+    }
+
+    fn get(params: GetParams) -> Value {
+        params.name.map_or_else(
+            || json!(parameters::snapshot()),
+            |name| json!(parameters::get(&name)),
+        )
+    }
+
+    fn set(&self, params: &SetParams) -> Result<Value> {
+        let packet = OscPacket::Message(OscMessage {
+            addr: format!("{PARAMETERS_PREFIX}{}", params.name),
+            args: vec![parameter_arg(&params.value)?],
+        });
+
+        self.router.send(packet, SOURCE)?;
+
+        Ok(Value::Null)
+    }
+}
+
+/// Binds the socket so only this user can connect, replacing one left behind by a loader that crashed
+///
+/// The socket is bound in a private directory and only moved into place once its permissions are set,
+/// the umask could let others connect in between otherwise
+#[cfg(unix)]
+fn bind(path: &str) -> Result<tokio::net::UnixListener> {
+    use std::{
+        fs::{DirBuilder, Permissions},
+        os::unix::fs::{DirBuilderExt, PermissionsExt},
+        path::Path,
+    };
+
+    if std::os::unix::net::UnixStream::connect(path).is_ok() {
+        bail!("Another loader is listening on {path}");
+    }
+
+    let dir = Path::new(path).with_file_name(format!(".vrc-osc-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir); // Left behind by a loader with the same pid
+    DirBuilder::new()
+        .mode(0o700)
+        .create(&dir)
+        .with_context(|| format!("Failed to create {}", dir.display()))?;
+
+    let private = dir.join("vrc-osc.sock");
+    let listener = tokio::net::UnixListener::bind(&private)
+        .with_context(|| format!("Failed to bind {path}"))
+        .and_then(|listener| {
+            std::fs::set_permissions(&private, Permissions::from_mode(0o600))?;
+            std::fs::rename(&private, path)?;
+            Ok(listener)
+        });
+
+    let _ = std::fs::remove_dir_all(&dir);
+
+    listener
+}
+
+/// Calls a method on the running loader and returns its result
+///
+/// # Errors
+///
+/// Will return `Err` if the loader isn't listening on the path or the call failed
+pub fn call(path: &str, method: &str, params: &Value) -> Result<Value> {
+    #[cfg(unix)]
+    let stream = std::os::unix::net::UnixStream::connect(path);
+
+    #[cfg(windows)]
+    let stream = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path);
+
+    let mut stream =
+        stream.with_context(|| format!("Failed to connect to {path}, is the loader running?"))?;
+
+    let request = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+    writeln!(stream, "{request}")?;
+
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+
+    let response = serde_json::from_str::<Value>(&line).context("Invalid response")?;
+    if let Some(error) = response.get("error") {
+        bail!("{}", error["message"].as_str().unwrap_or("Unknown error"));
+    }
+
+    Ok(response["result"].clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_a_missing_id_is_a_notification() {
+        let parse = |line| serde_json::from_str::<Request>(line).unwrap().id;
+
+        assert_eq!(parse(r#"{"jsonrpc":"2.0","method":"status"}"#), None);
+        assert_eq!(
+            parse(r#"{"jsonrpc":"2.0","id":null,"method":"status"}"#),
+            Some(Value::Null)
+        );
+        assert_eq!(
+            parse(r#"{"jsonrpc":"2.0","id":1,"method":"status"}"#),
+            Some(json!(1))
+        );
+    }
+}
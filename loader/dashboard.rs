@@ -0,0 +1,294 @@
+//! A local web UI showing the plugins, the avatar's parameters and the chatbox, and editing configs
+
+use std::{
+    collections::BTreeSet,
+    io::Cursor,
+    net::{SocketAddr, UdpSocket},
+    path::PathBuf,
+    sync::Arc,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, Server};
+use tokio::runtime::Handle;
+
+use crate::{avatar, host, parameters, plugin::Plugin, reload, router, Config, RunningPlugins};
+
+const PAGE: &str = include_str!("dashboard.html");
+
+/// What the dashboard reads from and changes in the running loader
+pub struct Dashboard {
+    /// The loader config file, saved without the environment and command line overrides
+    pub path:          PathBuf,
+    /// The config the loader is running with
+    pub config:        Config,
+    pub plugins:       RunningPlugins,
+    pub loader_socket: Arc<UdpSocket>,
+}
+
+type Body = Response<Cursor<Vec<u8>>>;
+
+fn with_type(response: Body, content_type: &str) -> Result<Body> {
+    let header =
+        Header::from_bytes("Content-Type", content_type).map_err(|()| anyhow!("Invalid header"))?;
+
+    Ok(response.with_header(header))
+}
+
+fn json_body(value: &Value) -> Result<Body> {
+    with_type(Response::from_string(value.to_string()), "application/json")
+}
+
+fn error_body(status: u16, error: &str) -> Body {
+    Response::from_string(error).with_status_code(status)
+}
+
+/// Web pages can reach the dashboard through DNS rebinding, but only with their own host name
+fn is_allowed_host(request: &Request, addr: SocketAddr) -> bool {
+    let Some(host) = request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv("Host"))
+        .map(|header| header.value.as_str())
+    else {
+        return false;
+    };
+
+    let name = host
+        .rsplit_once(':')
+        .filter(|(_, port)| port.parse::<u16>().is_ok())
+        .map_or(host, |(name, _)| name);
+
+    host == addr.to_string() || matches!(name, "localhost" | "127.0.0.1" | "[::1]")
+}
+
+/// Filenames come from requests, so only libraries next to the executable are opened
+fn open_plugin(filename: &str, config: &Config) -> Result<Plugin> {
+    if !crate::get_plugin_names()?
+        .iter()
+        .any(|name| name == filename)
+    {
+        bail!("There's no {filename} plugin next to the executable");
+    }
+
+    crate::open_plugin(filename, config)
+}
+
+impl Dashboard {
+    /// Serves the dashboard from a background thread, returns the address it's listening on
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if couldn't bind the address or wasn't called from the loader's runtime
+    pub fn serve(mut self, addr: &str) -> Result<SocketAddr> {
+        let runtime = Handle::try_current().context("The dashboard needs the loader's runtime")?;
+        let server =
+            Server::http(addr).map_err(|error| anyhow!("Failed to bind {addr}: {error}"))?;
+        let addr = server
+            .server_addr()
+            .to_ip()
+            .ok_or_else(|| anyhow!("The dashboard isn't listening on an IP address"))?;
+
+        std::thread::spawn(move || {
+            for request in server.incoming_requests() {
+                if !is_allowed_host(&request, addr) {
+                    if let Err(error) = request.respond(error_body(403, "Unknown host")) {
+                        tracing::error!("Dashboard: {error}");
+                    }
+
+                    continue;
+                }
+
+                if let Err(error) = self.respond(request, &runtime) {
+                    tracing::error!("Dashboard: {error}");
+                }
+            }
+        });
+
+        Ok(addr)
+    }
+
+    fn respond(&mut self, mut request: Request, runtime: &Handle) -> Result<()> {
+        let method = request.method().clone();
+        let url = request.url().to_owned();
+
+        let body = if method == Method::Post {
+            // Browsers can't send JSON to another origin without asking first, which is never allowed
+            let is_json = request.headers().iter().any(|header| {
+                header.field.equiv("Content-Type")
+                    && header.value.as_str().starts_with("application/json")
+            });
+
+            if !is_json {
+                request.respond(error_body(415, "Expected JSON"))?;
+                return Ok(());
+            }
+
+            let mut text = String::new();
+            request.as_reader().read_to_string(&mut text)?;
+            serde_json::from_str(&text).ok()
+        } else {
+            None
+        };
+
+        let plugin_config = url
+            .strip_prefix("/api/plugins/")
+            .and_then(|path| path.strip_suffix("/config"));
+
+        let result = match (&method, url.as_str(), plugin_config, body) {
+            (Method::Get, "/", _, _) => with_type(Response::from_string(PAGE), "text/html"),
+            (Method::Get, "/api/status", _, _) => json_body(&self.status()),
+            (Method::Get, "/api/plugins", _, _) => {
+                self.available().and_then(|json| json_body(&json))
+            }
+            (Method::Get, "/api/config", _, _) => self.config().and_then(|json| json_body(&json)),
+            (Method::Post, "/api/config", _, Some(body)) => self
+                .save_config(body, runtime)
+                .and_then(|json| json_body(&json)),
+            (Method::Get, _, Some(filename), _) => self
+                .plugin_config(filename)
+                .and_then(|json| json_body(&json)),
+            (Method::Post, _, Some(filename), Some(body)) => self
+                .save_plugin_config(filename, &body, runtime)
+                .and_then(|json| json_body(&json)),
+            (Method::Post, ..) => Ok(error_body(400, "Invalid JSON")),
+            _ => Ok(error_body(404, "Not found")),
+        };
+
+        let response = result.unwrap_or_else(|error| error_body(400, &format!("{error:#}")));
+        request.respond(response)?;
+
+        Ok(())
+    }
+
+    /// The running plugins and what the loader knows about the avatar and chatbox
+    fn status(&self) -> Value {
+        let plugins = self.plugins.read().expect("Failed to read plugins").clone();
+        let plugins = plugins
+            .iter()
+            .map(|running| running.describe())
+            .collect::<Vec<_>>();
+
+        json!({
+            "plugins": plugins,
+            "avatar": avatar::current(),
+            "parameters": parameters::snapshot(),
+            "chatbox": router::chatbox(),
+        })
+    }
+
+    /// Every plugin next to the executable, to enable or configure
+    fn available(&self) -> Result<Value> {
+        let plugins = crate::get_plugins()?
+            .iter()
+            .map(|plugin| {
+                json!({
+                    "filename": plugin.filename,
+                    "name": plugin.metadata.name,
+                    "version": plugin.metadata.version,
+                    "description": plugin.metadata.description,
+                    "enabled": self.config.enabled.contains(&plugin.filename),
+                    "configurable": plugin.config().is_some(),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        Ok(Value::Array(plugins))
+    }
+
+    /// The saved loader config, without the overrides
+    fn config(&self) -> Result<Value> {
+        let config = Config::read(&self.path).unwrap_or_default();
+
+        Ok(serde_json::to_value(config)?)
+    }
+
+    /// Saves the loader config and starts or unloads the plugins that were enabled or disabled
+    ///
+    /// Returns the other keys that changed, which only apply once the loader restarts
+    fn save_config(&mut self, body: Value, runtime: &Handle) -> Result<Value> {
+        let new = serde_json::from_value::<Config>(body).context("Invalid config")?;
+        let available = crate::get_plugin_names()?;
+        if let Some(unknown) = new.enabled.iter().find(|name| !available.contains(name)) {
+            bail!("There's no {unknown} plugin next to the executable");
+        }
+
+        let saved = serde_json::to_value(Config::read(&self.path).unwrap_or_default())?;
+        new.write(&self.path)?;
+
+        let running = self.config.enabled.iter().cloned().collect::<BTreeSet<_>>();
+        let enabled = new.enabled.iter().cloned().collect::<BTreeSet<_>>();
+        self.config.enabled.clone_from(&new.enabled);
+        host::set_config(&self.config);
+
+        runtime.block_on(async {
+            for filename in running.difference(&enabled) {
+                tracing::info!("Unloading {filename}");
+                if let Err(error) =
+                    reload::unload(filename, &self.plugins, &self.loader_socket).await
+                {
+                    tracing::error!("Failed to unload {filename}: {error:#}");
+                }
+            }
+
+            for filename in enabled.difference(&running) {
+                tracing::info!("Loading {filename}");
+                if let Err(error) =
+                    reload::reload(filename, &self.plugins, &self.loader_socket, &self.config).await
+                {
+                    tracing::error!("Failed to load {filename}: {error:#}");
+                }
+            }
+        });
+
+        let new = serde_json::to_value(&new)?;
+        let restart = new
+            .as_object()
+            .into_iter()
+            .flatten()
+            .filter(|(key, value)| *key != "enabled" && saved.get(key.as_str()) != Some(value))
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+
+        Ok(json!({ "restart": restart }))
+    }
+
+    fn plugin_config(&self, filename: &str) -> Result<Value> {
+        let plugin = open_plugin(filename, &self.config)?;
+        let name = &plugin.metadata.name;
+
+        plugin
+            .config()
+            .ok_or_else(|| anyhow!("{name} doesn't have a config"))?
+    }
+
+    /// Saves a plugin's config and reloads it if it's running so it reads the new config
+    fn save_plugin_config(&self, filename: &str, body: &Value, runtime: &Handle) -> Result<Value> {
+        let plugin = open_plugin(filename, &self.config)?;
+        let name = &plugin.metadata.name;
+
+        plugin
+            .set_config(body)
+            .ok_or_else(|| anyhow!("{name} doesn't have a config"))??;
+
+        let running = self
+            .plugins
+            .read()
+            .expect("Failed to read plugins")
+            .iter()
+            .any(|running| running.plugin.filename == filename);
+
+        if running {
+            tracing::info!("Reloading {filename} with its new config");
+            runtime.block_on(reload::reload(
+                filename,
+                &self.plugins,
+                &self.loader_socket,
+                &self.config,
+            ))?;
+        }
+
+        Ok(json!({ "restart": [] }))
+    }
+}
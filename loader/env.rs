@@ -0,0 +1,175 @@
+//! Overrides config fields with environment variables
+//!
+//! `VRC_OSC_BIND_ADDR` for the loader or `VRC_OSC_SPOTIFY_CLIENT` for the Spotify plugin,
+//! with `__` between nested keys like `VRC_OSC_RESTART__MODE`
+
+use std::fmt;
+
+use anyhow::{bail, Context, Result};
+use serde::{de::DeserializeOwned, Serialize};
+
+/// The loader's prefix, plugins add their name after it with [`plugin_prefix`]
+pub const PREFIX: &str = "VRC_OSC_";
+
+#[derive(Debug)]
+pub struct UnknownKey(pub String);
+
+impl fmt::Display for UnknownKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Unknown config key {}", self.0)
+    }
+}
+
+impl std::error::Error for UnknownKey {}
+
+/// `VRC_OSC_SPOTIFY_` for the `Spotify` plugin
+#[must_use]
+pub fn plugin_prefix(name: &str) -> String {
+    format!("{PREFIX}{}_", name.to_uppercase())
+}
+
+/// The environment variable overriding a key, with `.` between nested keys
+#[must_use]
+pub fn var_name(prefix: &str, key: &str) -> String {
+    format!("{prefix}{}", key.replace('.', "__").to_uppercase())
+}
+
+/// Parses a TOML value, anything that isn't valid TOML is a string so they don't need quoting
+fn parse_value(value: &str) -> toml::Value {
+    toml::from_str::<toml::Table>(&format!("value = {value}"))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(value.into()))
+}
+
+fn get_mut<'a>(table: &'a mut toml::Table, path: &[&str]) -> Option<&'a mut toml::Table> {
+    path.iter()
+        .try_fold(table, |table, key| table.get_mut(*key)?.as_table_mut())
+}
+
+/// Sets a key, with `.` between nested keys, to a TOML value
+///
+/// # Errors
+///
+/// Will return `Err` if the key is unknown, an [`UnknownKey`], or the value has the wrong type
+pub fn set<T: Serialize + DeserializeOwned>(config: &T, key: &str, value: &str) -> Result<T> {
+    let path = key.split('.').collect::<Vec<_>>();
+    let (last, parents) = path.split_last().context("None")?;
+
+    let table = toml::Table::try_from(config)?;
+    let with_value = |value| {
+        let mut table = table.clone();
+        let parent = get_mut(&mut table, parents).ok_or_else(|| UnknownKey(key.into()))?;
+        parent.insert((*last).into(), value);
+
+        toml::Value::Table(table)
+            .try_into::<T>()
+            .with_context(|| format!("Invalid value for {key}"))
+    };
+
+    let config = match parse_value(value) {
+        toml::Value::String(value) => with_value(toml::Value::String(value))?,
+        // A string field set to something like `1234` or `true`
+        typed => with_value(typed)
+            .or_else(|error| with_value(toml::Value::String(value.into())).map_err(|_| error))?,
+    };
+
+    // Unknown keys are ignored when deserializing
+    let mut table = toml::Table::try_from(&config)?;
+    let known = get_mut(&mut table, parents).is_some_and(|parent| parent.contains_key(*last));
+    if !known {
+        bail!(UnknownKey(key.into()));
+    }
+
+    Ok(config)
+}
+
+/// Overrides the config with every environment variable starting with the prefix
+///
+/// Variables that don't match a key are ignored, they may belong to the loader or another plugin
+///
+/// # Errors
+///
+/// Will return `Err` if a variable has the wrong type for its key
+pub fn apply<T: Serialize + DeserializeOwned>(mut config: T, prefix: &str) -> Result<T> {
+    for (name, value) in std::env::vars() {
+        let Some(key) = name.strip_prefix(prefix) else {
+            continue; // Not ours
+        };
+
+        let key = key.to_lowercase().replace("__", ".");
+        match set(&config, &key, &value) {
+            Ok(new_config) => config = new_config,
+            Err(error) if error.is::<UnknownKey>() => {} // Another plugin's
+            Err(error) => return Err(error.context(format!("Invalid {name}"))),
+        }
+    }
+
+    Ok(config)
+}
+
+/// Fails listing what to set for the required keys that are missing, instead of prompting for them
+///
+/// # Errors
+///
+/// Will return `Err` if any key is missing
+pub fn require(name: &str, prefix: &str, missing: &[&str]) -> Result<()> {
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    let vars = missing
+        .iter()
+        .map(|key| format!("  {} ({key})", var_name(prefix, key)))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    bail!("{name} is missing required config, set these in its config file or environment:\n{vars}")
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+    struct Config {
+        username: String,
+        port:     u16,
+        enabled:  bool,
+    }
+
+    #[test]
+    fn values_are_parsed_for_their_field() {
+        let config = set(&Config::default(), "port", "9000").unwrap();
+        let config = set(&config, "enabled", "true").unwrap();
+        let config = set(&config, "username", "name").unwrap();
+
+        assert_eq!(
+            config,
+            Config {
+                username: "name".into(),
+                port:     9000,
+                enabled:  true,
+            }
+        );
+    }
+
+    #[test]
+    fn numeric_looking_strings_stay_strings() {
+        let config = set(&Config::default(), "username", "1234").unwrap();
+        assert_eq!(config.username, "1234");
+
+        let config = set(&config, "username", "true").unwrap();
+        assert_eq!(config.username, "true");
+    }
+
+    #[test]
+    fn wrong_types_and_unknown_keys_are_errors() {
+        assert!(set(&Config::default(), "port", "name").is_err());
+
+        let error = set(&Config::default(), "missing", "1").unwrap_err();
+        assert!(error.is::<UnknownKey>());
+    }
+}
@@ -0,0 +1,77 @@
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::pattern::Pattern;
+
+/// Another OSC app that receives VRChat's packets through the loader
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ForwardTarget {
+    /// Where the app listens, such as `127.0.0.1:9002`
+    pub addr:        String,
+    /// Only packets with a message matching one of these are forwarded, everything when empty
+    #[serde(default)]
+    pub filters:     Vec<String>,
+    /// Packets the app sends here are relayed on to VRChat
+    pub listen_addr: Option<String>,
+}
+
+/// A resolved [`ForwardTarget`]
+pub struct Forward {
+    pub name:    String,
+    pub addr:    SocketAddr,
+    pub filters: Vec<Pattern>,
+    /// Bound to the target's `listen_addr`
+    pub socket:  Option<UdpSocket>,
+}
+
+impl Forward {
+    /// # Errors
+    ///
+    /// Will return `Err` if an address couldn't be resolved or bound, or a filter is invalid
+    pub fn new(target: &ForwardTarget) -> Result<Self> {
+        let addr = target
+            .addr
+            .to_socket_addrs()?
+            .next()
+            .with_context(|| format!("Failed to resolve {}", target.addr))?;
+
+        let filters = if target.filters.is_empty() {
+            vec![Pattern::new("//*")?] // Everything
+        } else {
+            target
+                .filters
+                .iter()
+                .map(|filter| {
+                    Pattern::new(filter)
+                        .with_context(|| format!("{} has an invalid filter", target.addr))
+                })
+                .collect::<Result<_>>()?
+        };
+
+        let socket = target
+            .listen_addr
+            .as_ref()
+            .map(|listen_addr| {
+                UdpSocket::bind(listen_addr)
+                    .with_context(|| format!("Failed to bind {listen_addr} for {}", target.addr))
+            })
+            .transpose()?;
+
+        Ok(Self {
+            name: target.addr.clone(),
+            addr,
+            filters,
+            socket,
+        })
+    }
+
+    /// Whether any of the addresses match the filters
+    #[must_use]
+    pub fn wants(&self, addrs: &[&str]) -> bool {
+        addrs
+            .iter()
+            .any(|addr| self.filters.iter().any(|filter| filter.matches(addr)))
+    }
+}
@@ -0,0 +1,306 @@
+use std::{
+    collections::BTreeMap,
+    io::IsTerminal,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        OnceLock,
+        PoisonError,
+        RwLock,
+    },
+    time::Duration,
+};
+
+use async_ffi::{FfiFuture, FutureExt};
+use rosc::OscType;
+use serde::{de::DeserializeOwned, Serialize};
+use tracing::Level;
+
+use crate::{
+    avatar::{self, Avatar},
+    logging,
+    parameters::{self, CachedParameter, Changes},
+    plugin::{free_string, FreeStringFn, RawChatMessage, RawStr, RawString},
+    ChatMessage,
+    Config,
+    RunningPlugins,
+};
+
+pub type AvatarFn = unsafe extern "C" fn() -> RawString;
+pub type ParameterFn = unsafe extern "C" fn(name: RawStr) -> RawString;
+pub type ParametersFn = unsafe extern "C" fn() -> RawString;
+pub type WaitParametersFn = unsafe extern "C" fn(after: u64, timeout_ms: u64) -> RawString;
+pub type InteractiveFn = unsafe extern "C" fn() -> bool;
+pub type LogFn = unsafe extern "C" fn(plugin: RawStr, level: u8, target: RawStr, message: RawStr);
+pub type ChatFn =
+    unsafe extern "C" fn(chatbox: RawStr, console: RawStr) -> FfiFuture<RawChatMessage>;
+pub type ConfigFn = unsafe extern "C" fn() -> RawString;
+
+/// Functions the loader hands to plugins in `load`, strings are JSON allocated by the loader
+#[repr(C)]
+pub struct HostVTable {
+    pub avatar: AvatarFn,
+    pub parameter: ParameterFn,
+    pub parameters: ParametersFn,
+    pub wait_parameters: WaitParametersFn,
+    pub interactive: InteractiveFn,
+    pub log: LogFn,
+    pub chat: ChatFn,
+    pub config: ConfigFn,
+    pub free_string: FreeStringFn,
+}
+
+/// The loader's implementation, plugins get their own copy of this crate so it must be passed in
+pub static HOST_VTABLE: HostVTable = HostVTable {
+    avatar: host_avatar,
+    parameter: host_parameter,
+    parameters: host_parameters,
+    wait_parameters: host_wait_parameters,
+    interactive: host_interactive,
+    log: host_log,
+    chat: host_chat,
+    config: host_config,
+    free_string,
+};
+
+static INTERACTIVE: AtomicBool = AtomicBool::new(true);
+
+/// Stops the loader and its plugins from prompting, for `--non-interactive`
+pub fn set_interactive(interactive: bool) {
+    INTERACTIVE.store(interactive, Ordering::Relaxed);
+}
+
+static CONFIG: RwLock<Option<Config>> = RwLock::new(None);
+static PLUGINS: RwLock<Option<RunningPlugins>> = RwLock::new(None);
+
+/// Shares the config the loader runs with, overrides and resolved addresses included, with plugins
+pub fn set_config(config: &Config) {
+    *CONFIG.write().unwrap_or_else(PoisonError::into_inner) = Some(config.clone());
+}
+
+/// Enables or disables a plugin in the shared config, like the control socket does at runtime
+pub fn set_enabled(filename: &str, enabled: bool) {
+    if let Some(config) = CONFIG
+        .write()
+        .unwrap_or_else(PoisonError::into_inner)
+        .as_mut()
+    {
+        config.enabled.retain(|enabled| enabled != filename);
+        if enabled {
+            config.enabled.push(filename.into());
+        }
+    }
+}
+
+/// The config shared with plugins, including the plugins enabled or disabled since the loader started
+#[must_use]
+pub fn shared_config() -> Option<Config> {
+    CONFIG
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone()
+}
+
+/// Shares the running plugins with plugins that chat, so they use the loaded chat providers
+pub fn set_plugins(plugins: &RunningPlugins) {
+    *PLUGINS.write().unwrap_or_else(PoisonError::into_inner) = Some(plugins.clone());
+}
+
+fn to_json(value: &impl Serialize) -> RawString {
+    serde_json::to_string(value).unwrap_or_default().into()
+}
+
+unsafe extern "C" fn host_avatar() -> RawString {
+    to_json(&avatar::current())
+}
+
+unsafe extern "C" fn host_parameter(name: RawStr) -> RawString {
+    to_json(&parameters::get(name.as_str()))
+}
+
+unsafe extern "C" fn host_parameters() -> RawString {
+    to_json(&parameters::snapshot())
+}
+
+unsafe extern "C" fn host_wait_parameters(after: u64, timeout_ms: u64) -> RawString {
+    to_json(&parameters::wait_changes(
+        after,
+        Duration::from_millis(timeout_ms),
+    ))
+}
+
+unsafe extern "C" fn host_interactive() -> bool {
+    INTERACTIVE.load(Ordering::Relaxed) && std::io::stdin().is_terminal()
+}
+
+unsafe extern "C" fn host_log(plugin: RawStr, level: u8, target: RawStr, message: RawStr) {
+    logging::plugin_event(
+        plugin.as_str(),
+        logging::level_from_raw(level),
+        target.as_str(),
+        message.as_str(),
+    );
+}
+
+unsafe extern "C" fn host_chat(chatbox: RawStr, console: RawStr) -> FfiFuture<RawChatMessage> {
+    let message = (chatbox.as_str().to_owned(), console.as_str().to_owned());
+    let plugins = match PLUGINS
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .as_ref()
+        .map(|plugins| plugins.read().map(|plugins| plugins.clone()))
+    {
+        Some(Ok(plugins)) => plugins,
+        Some(Err(_)) => {
+            // Panicking here would unwind across the FFI boundary, send the message as is instead
+            tracing::error!("Failed to read plugins, sending the chat message without providers");
+            Vec::new()
+        }
+        None => Vec::new(),
+    };
+
+    async move {
+        let (chatbox, console) = crate::chat_message(&message, &plugins).await;
+        RawChatMessage {
+            chatbox: chatbox.into(),
+            console: console.into(),
+        }
+    }
+    .into_ffi()
+}
+
+unsafe extern "C" fn host_config() -> RawString {
+    to_json(&*CONFIG.read().unwrap_or_else(PoisonError::into_inner))
+}
+
+static HOST: OnceLock<&'static HostVTable> = OnceLock::new();
+
+#[doc(hidden)]
+pub fn set_host(host: &'static HostVTable) {
+    let _ = HOST.set(host); // Already set if the plugin was restarted
+}
+
+fn take_string(host: &HostVTable, string: RawString) -> String {
+    let owned = string.as_str().to_owned();
+    unsafe { (host.free_string)(string) };
+
+    owned
+}
+
+/// Calls into the loader and decodes the JSON it returns, `None` outside of the loader
+fn call<T: DeserializeOwned>(call: impl FnOnce(&HostVTable) -> RawString) -> Option<T> {
+    let host = HOST.get()?;
+    let json = take_string(host, call(host));
+
+    serde_json::from_str(&json).ok()
+}
+
+/// Whether prompting is allowed, not with `--non-interactive` or without a terminal
+///
+/// Plugins should fail listing what to configure instead, see [`crate::env::require`]
+#[must_use]
+pub fn interactive() -> bool {
+    HOST.get().map_or_else(
+        || unsafe { host_interactive() },
+        |host| unsafe { (host.interactive)() },
+    )
+}
+
+/// Logs an event in the loader, plugins use `tracing` which forwards here once loaded
+pub fn log(plugin: &str, level: Level, target: &str, message: &str) {
+    let Some(host) = HOST.get() else {
+        return; // Outside of the loader
+    };
+
+    unsafe {
+        (host.log)(
+            RawStr::new(plugin),
+            logging::level_to_raw(level),
+            RawStr::new(target),
+            RawStr::new(message),
+        );
+    };
+}
+
+/// Runs the message through the chat providers the loader is running, unchanged outside of the loader
+pub async fn chat(message: &ChatMessage) -> ChatMessage {
+    let Some(host) = HOST.get() else {
+        return message.clone();
+    };
+
+    let (chatbox, console) = message;
+    let message = unsafe { (host.chat)(RawStr::new(chatbox), RawStr::new(console)) }.await;
+
+    (
+        take_string(host, message.chatbox),
+        take_string(host, message.console),
+    )
+}
+
+/// The loader config with its environment and command line overrides, `None` outside of the loader
+///
+/// Plugins should use this instead of reading the loader's config file, which may not be the default one
+#[must_use]
+pub fn config() -> Option<Config> {
+    call(|host| unsafe { (host.config)() }).flatten()
+}
+
+/// The current avatar and its parameters as discovered by the loader
+///
+/// Returns an unknown avatar outside of the loader or before VRChat reported one, see [`Avatar::is_known`]
+#[must_use]
+pub fn avatar() -> Avatar {
+    call(|host| unsafe { (host.avatar)() }).unwrap_or_default()
+}
+
+/// The last value the loader routed for a parameter, by its name after `/avatar/parameters/`
+///
+/// Values are forgotten when the avatar changes
+#[must_use]
+pub fn parameter(name: &str) -> Option<CachedParameter> {
+    call(|host| unsafe { (host.parameter)(RawStr::new(name)) }).flatten()
+}
+
+/// Same as [`parameter`] but only the value, to compare with what a plugin is about to send
+#[must_use]
+pub fn parameter_value(name: &str) -> Option<OscType> {
+    parameter(name).map(|parameter| parameter.value.into())
+}
+
+/// Every parameter value the loader routed since the avatar changed
+#[must_use]
+pub fn parameters() -> BTreeMap<String, CachedParameter> {
+    call(|host| unsafe { (host.parameters)() }).unwrap_or_default()
+}
+
+/// Parameters that changed since the last call to [`ParameterChanges::next`]
+pub struct ParameterChanges {
+    seq: u64,
+}
+
+/// Starts listening for parameter changes from now on
+#[must_use]
+pub fn parameter_changes() -> ParameterChanges {
+    let changes = wait_parameters(u64::MAX, Duration::ZERO);
+
+    ParameterChanges { seq: changes.seq }
+}
+
+fn wait_parameters(after: u64, timeout: Duration) -> Changes {
+    let timeout_ms = u64::try_from(timeout.as_millis()).unwrap_or(u64::MAX);
+    let changes = call(|host| unsafe { (host.wait_parameters)(after, timeout_ms) });
+
+    changes.unwrap_or_else(|| {
+        std::thread::sleep(timeout); // Don't busy loop outside of the loader
+        Changes::default()
+    })
+}
+
+impl ParameterChanges {
+    /// Blocks until parameters change or the timeout passes, use `spawn_blocking` from async code
+    pub fn next(&mut self, timeout: Duration) -> Vec<(String, CachedParameter)> {
+        let changes = wait_parameters(self.seq, timeout);
+        self.seq = self.seq.max(changes.seq);
+
+        changes.changes
+    }
+}
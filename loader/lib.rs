@@ -1,37 +1,159 @@
 use std::{
     ffi::OsStr,
-    net::{SocketAddr, UdpSocket},
+    net::UdpSocket,
+    path::Path,
+    sync::{Arc, RwLock},
+    time::Instant,
 };
 
 use anyhow::{Context, Result};
-use async_ffi::FfiFuture;
+#[doc(hidden)]
+pub use async_ffi;
 use derive_config::DeriveTomlConfig;
-use libloading::{Library, Symbol};
 use path_absolutize::Absolutize;
 use serde::{Deserialize, Serialize};
-use tokio::runtime::Handle;
 use walkdir::{DirEntry, WalkDir};
 
+use crate::{
+    forward::ForwardTarget,
+    logging::LogConfig,
+    plugin::{NotAPlugin, Plugin},
+    rate_limit::RateLimit,
+    supervisor::{RestartPolicy, RunningPlugin},
+    tcp::Framing,
+    validation::ValidationMode,
+};
+
+pub mod avatar;
+pub mod avatar_config;
+pub mod control;
+pub mod dashboard;
+pub mod env;
+pub mod forward;
+pub mod host;
+pub mod logging;
+pub mod metrics;
+pub mod oscquery;
+pub mod parameters;
+pub mod pattern;
+pub mod plugin;
+pub mod rate_limit;
+pub mod reload;
+pub mod router;
+pub mod schedule;
+pub mod sender;
+pub mod session;
+pub mod shutdown;
+pub mod supervisor;
+pub mod tcp;
+pub mod testing;
+pub mod validation;
+
 pub const CARGO_PKG_VERSION: &str = env!("CARGO_PKG_VERSION");
 pub const CARGO_PKG_HOMEPAGE: &str = env!("CARGO_PKG_HOMEPAGE");
 
 #[derive(Clone, Debug, DeriveTomlConfig, Deserialize, Serialize)]
+#[serde(default)]
 pub struct Config {
-    pub enabled:   Vec<String>,
+    pub enabled: Vec<String>,
     pub bind_addr: String,
     pub send_addr: String,
+    pub restart: RestartPolicy,
+    /// Reloads plugins when their library is rebuilt
+    pub hot_reload: bool,
+    /// Advertises the loader over OSCQuery so VRChat finds it, use with a dynamic `bind_addr` port
+    pub oscquery: bool,
+    /// VRChat's OSCQuery URL, discovered over mDNS when unset
+    pub oscquery_url: Option<String>,
+    /// VRChat's OSC config directory, used to find avatar parameters without OSCQuery
+    pub osc_config_dir: Option<String>,
+    /// Checks outgoing parameters against the current avatar
+    pub validation: ValidationMode,
+    /// Limits how fast all plugins together can send to matching addresses
+    pub rate_limits: Vec<RateLimit>,
+    /// Other OSC apps that receive VRChat's packets through the loader
+    pub forward: Vec<ForwardTarget>,
+    /// Accepts OSC over TCP from other apps, which receive everything VRChat sends, and plugins
+    pub tcp_addr: Option<String>,
+    pub tcp_framing: Framing,
+    /// Records every routed packet to this JSON Lines session file
+    pub record: Option<String>,
+    /// Feeds a recorded session to the plugins as if VRChat sent it
+    pub replay: Option<String>,
+    /// How much faster than recorded to replay, at least `0.01`, or `0` for as fast as possible
+    #[serde(deserialize_with = "crate::session::deserialize_speed")]
+    pub replay_speed: f64,
+    pub log: LogConfig,
+    /// Serves Prometheus metrics on `http://<metrics_addr>/metrics`, like `127.0.0.1:9100`
+    pub metrics_addr: Option<String>,
+    /// Serves a web dashboard to see the plugins and edit configs, like `127.0.0.1:8080`
+    pub dashboard_addr: Option<String>,
+    /// Accepts JSON-RPC commands, like from `vrc-osc ctl`, on a Unix socket or named pipe
+    pub control: bool,
+    /// The control socket path, `vrc-osc.sock` in the runtime directory or `\\.\pipe\vrc-osc` by default
+    pub control_socket: Option<String>,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
-            enabled:   Vec::default(),
+            enabled: Vec::default(),
             bind_addr: "0.0.0.0:9001".into(),
             send_addr: "127.0.0.1:9000".into(),
+            restart: RestartPolicy::default(),
+            hot_reload: false,
+            oscquery: false,
+            oscquery_url: None,
+            osc_config_dir: None,
+            validation: ValidationMode::default(),
+            rate_limits: RateLimit::defaults(),
+            forward: Vec::new(),
+            tcp_addr: None,
+            tcp_framing: Framing::default(),
+            record: None,
+            replay: None,
+            replay_speed: 1.0,
+            log: LogConfig::default(),
+            metrics_addr: None,
+            dashboard_addr: None,
+            control: true,
+            control_socket: None,
         }
     }
 }
 
+impl Config {
+    /// Reads the config from a file other than the default next to the exe
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the file couldn't be read or is invalid
+    pub fn read(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+
+        toml::from_str(&text).with_context(|| format!("Invalid config {}", path.display()))
+    }
+
+    /// # Errors
+    ///
+    /// Will return `Err` if the config couldn't be serialized or the file written
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let text = toml::to_string_pretty(self)?;
+        std::fs::write(path, text).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Sets a key, with `.` between nested keys, to a TOML value
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the key is unknown or the value has the wrong type
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        *self = env::set(self, key, value)?;
+        Ok(())
+    }
+}
+
 /// # Errors
 ///
 /// Will return `Err` if couldn't get the current exe or dir path
@@ -66,6 +188,24 @@ pub fn get_plugin_names() -> Result<Vec<String>> {
     Ok(libraries)
 }
 
+/// Opens every plugin next to the exe, skipping libraries that aren't compatible plugins
+///
+/// # Errors
+///
+/// Will return `Err` if couldn't get the current exe or dir path
+pub fn get_plugins() -> Result<Vec<Plugin>> {
+    let mut plugins = Vec::new();
+    for filename in get_plugin_names()? {
+        match Plugin::new(&filename) {
+            Ok(plugin) => plugins.push(plugin),
+            Err(error) if error.is::<NotAPlugin>() => {} // Not a plugin
+            Err(error) => tracing::warn!("Skipping {filename}: {error:#}"),
+        }
+    }
+
+    Ok(plugins)
+}
+
 /// # Errors
 ///
 /// Will return `Err` if couldn't get the current exe or dir path
@@ -79,81 +219,80 @@ pub fn get_plugin_path(path: String) -> Result<String> {
     Ok(path.to_str().context("None")?.to_owned())
 }
 
+/// Opens the plugin from a shadow copy when hot reloading, so the library can be rebuilt
+///
 /// # Errors
 ///
-/// Will return `Err` if couldn't get the current exe or dir path
+/// Will return `Err` if the plugin couldn't be copied or opened
+pub fn open_plugin(filename: &str, config: &Config) -> Result<Plugin> {
+    if config.hot_reload {
+        reload::open_fresh(filename)
+    } else {
+        Plugin::new(filename)
+    }
+}
+
+/// Gives the plugin its own socket connected to the loader and starts supervising it
 ///
-/// # Panics
+/// # Errors
 ///
-/// Will panic if a plugin fails to load
-pub fn load_plugins(names: Vec<String>, config: &Config) -> Result<Vec<SocketAddr>> {
-    type LoadFn = fn(socket: UdpSocket);
+/// Will return `Err` if couldn't bind or connect the socket
+pub fn start_plugin(plugin: Plugin, config: &Config) -> Result<RunningPlugin> {
+    let socket = UdpSocket::bind("127.0.0.1:0")?; // Dynamic port
+    let loader_addr = config.bind_addr.replace("0.0.0.0", "127.0.0.1");
+    socket.connect(loader_addr)?;
+
+    supervisor::spawn(Arc::new(plugin), socket, config.restart)
+}
 
-    let mut addrs = Vec::new();
+/// The plugins currently running, shared with the hot reload watcher
+pub type RunningPlugins = Arc<RwLock<Vec<Arc<RunningPlugin>>>>;
+
+/// # Errors
+///
+/// Will return `Err` if couldn't get the current exe or dir path
+pub fn load_plugins(names: Vec<String>, config: &Config) -> Result<Vec<Arc<RunningPlugin>>> {
+    let mut plugins = Vec::new();
     for name in names {
         if !config.enabled.contains(&name) {
             continue; // Skip disabled plugins
         }
 
-        let path = get_plugin_path(name)?;
-        let socket = UdpSocket::bind("127.0.0.1:0")?; // Dynamic port
-        let loader_addr = config.bind_addr.replace("0.0.0.0", "127.0.0.1");
-        let plugin_addr = socket.local_addr()?;
-        socket.connect(loader_addr)?;
-        addrs.push(plugin_addr);
-
-        tokio::spawn(async move {
-            let plugin = unsafe { Library::new(path).expect("Failed to get the plugin") };
-            let load_fn: Symbol<LoadFn> = unsafe {
-                plugin
-                    .get(b"load")
-                    .expect("Failed to get the load function")
-            };
-
-            load_fn(socket);
-        });
+        let plugin = match open_plugin(&name, config) {
+            Ok(plugin) => plugin,
+            Err(error) => {
+                tracing::error!("Failed to load {name}: {error:#}");
+                continue;
+            }
+        };
+
+        plugins.push(Arc::new(start_plugin(plugin, config)?));
     }
 
-    Ok(addrs)
+    Ok(plugins)
 }
 
 pub type ChatMessage = (String, String);
 
-/// # Errors
-///
-/// Will return `Err` if couldn't get the current exe or dir path
-pub async fn chat_message(
-    message: &ChatMessage,
-    names: &[String],
-    config: &Config,
-) -> Result<ChatMessage> {
-    type ChatFn =
-        fn(chatbox: String, console: String, handle: Handle) -> FfiFuture<Result<ChatMessage>>;
-
+/// Runs the message through the running chat providers in load order, plugins call it with [`host::chat`]
+pub async fn chat_message(message: &ChatMessage, plugins: &[Arc<RunningPlugin>]) -> ChatMessage {
     let mut message = message.clone();
-    for name in names.iter().cloned() {
-        if !config.enabled.contains(&name) {
-            continue; // Skip disabled plugins
+    for running in plugins {
+        let plugin = &running.plugin;
+        let started = Instant::now();
+        let result = plugin.chat(&message).await;
+        if result.is_some() {
+            metrics::chat_latency(&plugin.metadata.name, started.elapsed());
         }
 
-        let path = get_plugin_path(name)?;
-        let plugin = unsafe { Library::new(path.clone()) }?;
-        let chat_fn = match unsafe { plugin.get(b"chat") } {
-            Ok(chat_fn) => chat_fn as Symbol<ChatFn>,
-            Err(_) => continue,
-        };
-
-        let (chatbox, console) = message.clone();
-        match chat_fn(chatbox, console, Handle::current()).await {
-            Ok(new_message) => message = new_message,
-            Err(error) => eprintln!("Chatbox Error: {error}"),
+        match result {
+            Some(Ok(new_message)) => message = new_message,
+            Some(Err(error)) => tracing::error!("{} failed to chat: {error}", plugin.filename),
+            None => {} // Not a chat provider
         }
-
-        // This appears to fix a random access violation?
-        continue;
     }
 
-    Ok(message)
+    message
 }
 
 /// # Errors
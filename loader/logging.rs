@@ -0,0 +1,239 @@
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Write},
+    io::IsTerminal,
+    path::Path,
+    sync::OnceLock,
+};
+
+use anyhow::{Context as _, Result};
+use serde::{Deserialize, Serialize};
+use tracing::{
+    field::{Field, Visit},
+    level_filters::LevelFilter,
+    Event,
+    Level,
+    Span,
+    Subscriber,
+};
+use tracing_appender::{
+    non_blocking::WorkerGuard,
+    rolling::{Builder, Rotation},
+};
+use tracing_subscriber::{
+    field::RecordFields,
+    fmt::{
+        format::{DefaultFields, Writer},
+        FormatFields,
+    },
+    layer::{Context, SubscriberExt},
+    util::SubscriberInitExt,
+    EnvFilter,
+    Layer,
+};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct LogConfig {
+    /// Level filter directives, like `info` or `info,loader::router=debug`
+    pub level:     String,
+    /// Level filters by plugin name, like `Spotify = "debug"`
+    pub plugins:   BTreeMap<String, String>,
+    /// Writes daily log files to a `logs` directory next to the config
+    pub files:     bool,
+    /// How many daily log files to keep
+    pub max_files: usize,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level:     "info".into(),
+            plugins:   BTreeMap::new(),
+            files:     true,
+            max_files: 7,
+        }
+    }
+}
+
+/// Plugins with their own level filter, checked before logging their events
+static PLUGIN_LEVELS: OnceLock<BTreeMap<String, LevelFilter>> = OnceLock::new();
+
+/// Logs to the terminal, and to rotating files in the directory when enabled
+///
+/// Keep the guard until exiting so the last lines are written to the file
+///
+/// # Errors
+///
+/// Will return `Err` if a level filter is invalid, the log directory couldn't be created,
+/// or logging was already initialized
+pub fn init(config: &LogConfig, dir: Option<&Path>) -> Result<Option<WorkerGuard>> {
+    let mut directives = config.level.clone();
+    let mut plugin_levels = BTreeMap::new();
+    for (name, level) in &config.plugins {
+        let level = level
+            .parse::<LevelFilter>()
+            .with_context(|| format!("Invalid log level for {name}: {level}"))?;
+
+        // Lets the plugin log more than the default level, less is filtered in `plugin_event`
+        write!(directives, ",[plugin{{name={name}}}]={level}")?;
+        plugin_levels.insert(name.clone(), level);
+    }
+
+    let _ = PLUGIN_LEVELS.set(plugin_levels);
+
+    let filter = EnvFilter::try_new(&directives)
+        .with_context(|| format!("Invalid log level: {directives}"))?;
+
+    let (file, guard) = match dir.filter(|_| config.files) {
+        Some(dir) => {
+            std::fs::create_dir_all(dir)?;
+            let appender = Builder::new()
+                .rotation(Rotation::DAILY)
+                .filename_prefix("vrc-osc")
+                .filename_suffix("log")
+                .max_log_files(config.max_files)
+                .build(dir)
+                .with_context(|| format!("Failed to log to {}", dir.display()))?;
+
+            let (writer, guard) = tracing_appender::non_blocking(appender);
+            let layer = tracing_subscriber::fmt::layer()
+                .with_ansi(false)
+                .fmt_fields(PlainFields::default())
+                .with_writer(writer);
+
+            (Some(layer), Some(guard))
+        }
+        None => (None, None),
+    };
+
+    let terminal = tracing_subscriber::fmt::layer()
+        .with_ansi(std::io::stderr().is_terminal())
+        .with_writer(std::io::stderr);
+
+    tracing_subscriber::registry()
+        .with(terminal)
+        .with(file)
+        .with(filter)
+        .try_init()?;
+
+    Ok(guard)
+}
+
+/// Span fields are formatted once per formatter type, the file needs its own to stay free of colors
+#[derive(Default)]
+struct PlainFields(DefaultFields);
+
+impl<'writer> FormatFields<'writer> for PlainFields {
+    fn format_fields<R: RecordFields>(&self, writer: Writer<'writer>, fields: R) -> fmt::Result {
+        self.0.format_fields(writer, fields)
+    }
+}
+
+/// The span everything the loader does for a plugin and everything it logs is in
+#[must_use]
+pub fn plugin_span(name: &str) -> Span {
+    tracing::info_span!("plugin", name)
+}
+
+/// Logs an event a plugin forwarded to the loader
+pub fn plugin_event(name: &str, level: Level, target: &str, message: &str) {
+    let filtered = PLUGIN_LEVELS
+        .get()
+        .and_then(|levels| levels.get(name))
+        .is_some_and(|max| level > *max);
+
+    if filtered {
+        return;
+    }
+
+    // Callsites are static so the level can't be passed through
+    let _span = plugin_span(name).entered();
+    match level {
+        Level::ERROR => tracing::error!(target: "plugin", module = target, "{message}"),
+        Level::WARN => tracing::warn!(target: "plugin", module = target, "{message}"),
+        Level::INFO => tracing::info!(target: "plugin", module = target, "{message}"),
+        Level::DEBUG => tracing::debug!(target: "plugin", module = target, "{message}"),
+        Level::TRACE => tracing::trace!(target: "plugin", module = target, "{message}"),
+    }
+}
+
+/// Levels are passed to the loader as `0` for errors up to `4` for traces
+#[must_use]
+pub const fn level_to_raw(level: Level) -> u8 {
+    match level {
+        Level::ERROR => 0,
+        Level::WARN => 1,
+        Level::INFO => 2,
+        Level::DEBUG => 3,
+        Level::TRACE => 4,
+    }
+}
+
+#[must_use]
+pub const fn level_from_raw(level: u8) -> Level {
+    match level {
+        0 => Level::ERROR,
+        1 => Level::WARN,
+        2 => Level::INFO,
+        3 => Level::DEBUG,
+        _ => Level::TRACE,
+    }
+}
+
+/// Collects the message and fields of an event into one line
+#[derive(Default)]
+struct MessageVisitor {
+    message: String,
+    fields:  String,
+}
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        let _ = if field.name() == "message" {
+            write!(self.message, "{value:?}")
+        } else {
+            write!(self.fields, " {}={value:?}", field.name())
+        };
+    }
+}
+
+/// Forwards the plugin's events to the loader, the plugin's copy of `tracing` has no subscriber of its own
+struct HostLayer {
+    name: &'static str,
+}
+
+impl<S: Subscriber> Layer<S> for HostLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+        visitor.message.push_str(&visitor.fields);
+
+        let metadata = event.metadata();
+        crate::host::log(
+            self.name,
+            *metadata.level(),
+            metadata.target(),
+            &visitor.message,
+        );
+    }
+}
+
+/// The filter the loader applies to the plugin's events, so they aren't formatted only to be dropped
+fn plugin_filter(name: &str) -> EnvFilter {
+    let config = crate::host::config()
+        .map(|config| config.log)
+        .unwrap_or_default();
+    let directives = config.plugins.get(name).unwrap_or(&config.level);
+
+    EnvFilter::try_new(directives).unwrap_or_else(|_| EnvFilter::new(LevelFilter::INFO.to_string()))
+}
+
+#[doc(hidden)]
+pub fn forward_to_host(name: &'static str) {
+    // Already set if the plugin was restarted
+    let _ = tracing_subscriber::registry()
+        .with(HostLayer { name })
+        .with(plugin_filter(name))
+        .try_init();
+}
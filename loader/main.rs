@@ -1,63 +1,465 @@
-use std::net::UdpSocket;
+use std::{
+    net::UdpSocket,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
 
-use anyhow::Result;
+use anyhow::{bail, Result};
+use clap::{Parser, Subcommand};
 use derive_config::DeriveTomlConfig;
 use inquire::Confirm;
-use loader::{Config, CARGO_PKG_HOMEPAGE};
-use rosc::decoder::MTU;
-use terminal_link::Link;
+use loader::{
+    avatar_config,
+    control::{self, Control},
+    dashboard::Dashboard,
+    env,
+    host,
+    logging,
+    metrics,
+    oscquery::{self, OscQuery},
+    plugin::Plugin,
+    router::Router,
+    shutdown,
+    Config,
+    CARGO_PKG_HOMEPAGE,
+};
+use rosc::{OscMessage, OscPacket, OscType};
+use serde_json::{json, Value};
+
+/// Dynamically loaded VRChat OSC plugins written in Rust
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    /// The loader config file, next to the executable by default
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+    /// Overrides the address the loader receives VRChat's packets on
+    #[arg(long, global = true)]
+    bind: Option<String>,
+    /// Overrides VRChat's address the loader sends to
+    #[arg(long, global = true)]
+    send: Option<String>,
+    /// Fails listing the missing config instead of prompting, also without a terminal
+    #[arg(long, global = true, env = "VRC_OSC_NON_INTERACTIVE")]
+    non_interactive: bool,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Runs the enabled plugins, the default
+    Run,
+    /// Lists, enables or disables plugins
+    #[command(subcommand)]
+    Plugins(PluginsCommand),
+    /// Shows or changes the loader config
+    #[command(subcommand)]
+    Config(ConfigCommand),
+    /// Sends an OSC message to VRChat, arguments are parsed as bools, ints, floats or strings
+    Send {
+        addr: String,
+        #[arg(allow_negative_numbers = true)]
+        args: Vec<String>,
+    },
+    /// Prints the OSC messages received on the bind address, in place of the loader
+    Monitor,
+    /// Controls the running loader through its control socket
+    #[command(subcommand)]
+    Ctl(CtlCommand),
+}
+
+#[derive(Subcommand)]
+enum PluginsCommand {
+    /// Lists the plugins next to the executable
+    List,
+    /// Enables a plugin by its name or filename
+    Enable { name: String },
+    /// Disables a plugin by its name or filename
+    Disable { name: String },
+}
+
+#[derive(Subcommand)]
+enum CtlCommand {
+    /// Prints the running plugins, the avatar and the last chatbox message
+    Status,
+    /// Starts a plugin by its name or filename until the loader restarts
+    Enable { name: String },
+    /// Unloads a running plugin by its name or filename until the loader restarts
+    Disable { name: String },
+    /// Sends a message to the chatbox
+    Chat {
+        text:   String,
+        /// Plays the notification sound
+        #[arg(long)]
+        notify: bool,
+    },
+    /// Prints a parameter's last value, or every parameter
+    Get { name: Option<String> },
+    /// Sets an avatar parameter, the value is parsed as a bool, int or float
+    Set {
+        name:  String,
+        #[arg(allow_negative_numbers = true)]
+        value: String,
+    },
+    /// Calls a method with JSON params
+    Call {
+        method: String,
+        params: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Prints the config as TOML
+    Show,
+    /// Sets a top-level key to a TOML value
+    Set { key: String, value: String },
+}
+
+impl Cli {
+    fn config_path(&self) -> Result<PathBuf> {
+        match &self.config {
+            Some(path) => Ok(path.clone()),
+            None => Ok(Config::path()?),
+        }
+    }
+
+    /// The config with the environment and command line overrides, which are never saved
+    fn with_overrides(&self, config: Config) -> Result<Config> {
+        let mut config = env::apply(config, env::PREFIX)?;
+        if let Some(bind) = &self.bind {
+            config.bind_addr.clone_from(bind);
+        }
+        if let Some(send) = &self.send {
+            config.send_addr.clone_from(send);
+        }
+
+        Ok(config)
+    }
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
     human_panic::setup_panic!();
 
+    let cli = Cli::parse();
+    let path = cli.config_path()?;
+    let command = cli.command.as_ref().unwrap_or(&Command::Run);
+    host::set_interactive(!cli.non_interactive);
+
+    // Only the loader writes log files, next to its config
+    let log = Config::read(&path)
+        .and_then(|config| env::apply(config, env::PREFIX))
+        .unwrap_or_default()
+        .log;
+    let log_dir = matches!(command, Command::Run).then(|| path.with_file_name("logs"));
+    let guard = logging::init(&log, log_dir.as_deref())?;
+
+    match command {
+        Command::Run => {
+            let config = if path.exists() {
+                Config::read(&path)?
+            } else if host::interactive() {
+                setup(&path)?
+            } else {
+                Config::default()
+            };
+
+            let config = cli.with_overrides(config)?;
+            if config.enabled.is_empty() {
+                env::require("The loader", env::PREFIX, &["enabled"])?;
+            }
+
+            let result = run(config, path).await;
+            if let Err(error) = &result {
+                tracing::error!("{error:#}");
+            }
+
+            // Plugins left running would keep the runtime from shutting down, flush the logs first
+            drop(guard);
+            std::process::exit(i32::from(result.is_err()));
+        }
+        Command::Plugins(command) => plugins(command, &path),
+        Command::Config(ConfigCommand::Show) => {
+            let config = cli.with_overrides(Config::read(&path)?)?;
+            print!("{}", toml::to_string_pretty(&config)?);
+            Ok(())
+        }
+        Command::Config(ConfigCommand::Set { key, value }) => {
+            let mut config = Config::read(&path).unwrap_or_default();
+            config.set(key, value)?;
+            config.write(&path)
+        }
+        Command::Send { addr, args } => {
+            let config = cli.with_overrides(Config::read(&path).unwrap_or_default())?;
+            send(&config, addr, args)
+        }
+        Command::Monitor => {
+            let config = cli.with_overrides(Config::read(&path).unwrap_or_default())?;
+            monitor(&config)
+        }
+        Command::Ctl(command) => {
+            let config = cli.with_overrides(Config::read(&path).unwrap_or_default())?;
+            ctl(command, &config)
+        }
+    }
+}
+
+/// Asks which plugins to enable and saves the new config
+fn setup(path: &Path) -> Result<Config> {
+    let mut config = Config::default();
+    for plugin in sorted_plugins()? {
+        let metadata = &plugin.metadata;
+        let prompt = format!("Would you like to enable the {} plugin", metadata.name);
+        let help = format!("v{} - {}", metadata.version, metadata.description);
+        if Confirm::new(&prompt)
+            .with_default(false)
+            .with_help_message(&help)
+            .prompt()?
+        {
+            config.enabled.push(plugin.filename);
+        }
+    }
+
+    if config.enabled.is_empty() {
+        println!("You must enable at least one plugin");
+        std::process::exit(1);
+    }
+
+    config.write(path)?;
+    Ok(config)
+}
+
+async fn run(mut config: Config, path: PathBuf) -> Result<()> {
     if loader::check_for_updates()? {
-        let link = Link::new("An update is available", CARGO_PKG_HOMEPAGE);
-        println!("{link}");
+        tracing::info!("An update is available: {CARGO_PKG_HOMEPAGE}");
     }
 
-    let config = if let Ok(config) = Config::load() {
-        config
+    // Reloads open shadow copies even without hot reloading
+    loader::reload::clean_shadow_copies();
+
+    if let Some(metrics_addr) = &config.metrics_addr {
+        let addr = metrics::serve(metrics_addr)?;
+        tracing::info!("Serving metrics on http://{addr}/metrics");
+    }
+
+    let loader_socket = Arc::new(UdpSocket::bind(&config.bind_addr)?);
+    let loader_addr = loader_socket.local_addr()?;
+    config.bind_addr = loader_addr.to_string(); // Resolve dynamic ports for the plugins
+    host::set_config(&config);
+    let plugin_names = loader::get_plugin_names()?;
+    let plugins = loader::load_plugins(plugin_names, &config)?;
+    let plugins = Arc::new(RwLock::new(plugins));
+    host::set_plugins(&plugins);
+    let router = Arc::new(Router::new(
+        loader_socket.clone(),
+        plugins.clone(),
+        &config,
+    )?);
+
+    let _oscquery = if config.oscquery {
+        oscquery::track_avatar(config.oscquery_url.clone());
+        Some(OscQuery::start(
+            loader_addr,
+            plugins.clone(),
+            router.forwarded(),
+        )?)
     } else {
-        let mut config = Config::default();
-        let mut plugins = loader::get_plugin_names()?;
-        plugins.sort();
-
-        for plugin in plugins {
-            let prompt = format!("Would you like to enable the {plugin} plugin");
-            if Confirm::new(&prompt).with_default(false).prompt()? {
-                config.enabled.push(plugin.clone());
-            }
+        if let Some(osc_dir) = avatar_config::osc_dir(config.osc_config_dir.as_deref()) {
+            avatar_config::track_avatar(osc_dir);
         }
 
-        if config.enabled.is_empty() {
-            println!("You must enable at least one plugin");
-            std::process::exit(1);
+        None
+    };
+
+    if let Some(dashboard_addr) = &config.dashboard_addr {
+        let dashboard = Dashboard {
+            path:          path.clone(),
+            config:        config.clone(),
+            plugins:       plugins.clone(),
+            loader_socket: loader_socket.clone(),
+        };
+
+        let addr = dashboard.serve(dashboard_addr)?;
+        tracing::info!("Serving the dashboard on http://{addr}");
+    }
+
+    if config.control {
+        let control = Control {
+            router:        router.clone(),
+            plugins:       plugins.clone(),
+            loader_socket: loader_socket.clone(),
+            config:        config.clone(),
+        };
+
+        // The loader is still usable without it, like when another loader is running
+        let path = control::path(&config);
+        match control.serve(&path) {
+            Ok(()) => tracing::info!("Listening for control commands on {path}"),
+            Err(error) => tracing::warn!("Control socket disabled: {error:#}"),
         }
+    }
+
+    if config.hot_reload {
+        loader::reload::watch(plugins.clone(), loader_socket.clone(), config.clone());
+    }
+
+    // The router blocks on its socket, so it runs on its own thread until the loader exits
+    let (stopped_tx, stopped_rx) = tokio::sync::oneshot::channel();
+    std::thread::spawn(move || stopped_tx.send(router.run()));
 
-        config.save()?;
-        config
+    let result = tokio::select! {
+        result = shutdown::signal() => result,
+        result = stopped_rx => result.unwrap_or_else(|error| Err(error.into())),
     };
 
-    let loader_socket = UdpSocket::bind(&config.bind_addr)?;
-    let plugin_names = loader::get_plugin_names()?;
-    let plugin_addrs = loader::load_plugins(plugin_names, &config)?;
+    tracing::info!("Shutting down");
+    if let Err(error) = shutdown::save_config(&path) {
+        tracing::error!("Failed to save the config: {error:#}");
+    }
 
-    loop {
-        let mut buf = [0u8; MTU];
-        let Ok((size, recv_addr)) = loader_socket.recv_from(&mut buf) else {
-            continue;
-        };
+    if !shutdown::stop_plugins(&plugins, &loader_socket).await {
+        tracing::warn!("Some plugins didn't unload, exiting without them");
+    }
+
+    result
+}
 
-        // Plugins -> VRChat
-        if plugin_addrs.contains(&recv_addr) {
-            loader_socket.send_to(&buf[..size], &config.send_addr)?;
-            continue;
+fn sorted_plugins() -> Result<Vec<Plugin>> {
+    let mut plugins = loader::get_plugins()?;
+    plugins.sort_by(|a, b| a.metadata.name.cmp(&b.metadata.name));
+
+    Ok(plugins)
+}
+
+fn plugins(command: &PluginsCommand, path: &Path) -> Result<()> {
+    let mut config = Config::read(path).unwrap_or_default();
+    let plugins = sorted_plugins()?;
+    let find = |name: &str| {
+        plugins.iter().find(|plugin| {
+            plugin.filename == name || plugin.metadata.name.eq_ignore_ascii_case(name)
+        })
+    };
+
+    match command {
+        PluginsCommand::List => {
+            for plugin in &plugins {
+                let metadata = &plugin.metadata;
+                let enabled = if config.enabled.contains(&plugin.filename) {
+                    "x"
+                } else {
+                    " "
+                };
+
+                println!(
+                    "[{enabled}] {} v{} ({}) - {}",
+                    metadata.name, metadata.version, plugin.filename, metadata.description
+                );
+            }
+
+            return Ok(());
+        }
+        PluginsCommand::Enable { name } => {
+            let Some(plugin) = find(name) else {
+                bail!("There's no {name} plugin next to the executable");
+            };
+
+            if !config.enabled.contains(&plugin.filename) {
+                config.enabled.push(plugin.filename.clone());
+            }
         }
+        PluginsCommand::Disable { name } => {
+            // Plugins that were removed can still be disabled by filename
+            let filename = find(name).map_or(name, |plugin| &plugin.filename);
+            if !config.enabled.contains(filename) {
+                bail!("The {name} plugin isn't enabled");
+            }
 
-        // VRChat -> Plugins
-        for plugin_addr in &plugin_addrs {
-            loader_socket.send_to(&buf[..size], plugin_addr)?;
+            config.enabled.retain(|enabled| enabled != filename);
         }
     }
+
+    config.write(path)
+}
+
+/// Parses a command line argument as the first OSC type it's valid for
+fn parse_arg(arg: &str) -> OscType {
+    arg.parse()
+        .map(OscType::Bool)
+        .or_else(|_| arg.parse().map(OscType::Int))
+        .or_else(|_| arg.parse().map(OscType::Float))
+        .unwrap_or_else(|_| OscType::String(arg.into()))
+}
+
+fn ctl(command: &CtlCommand, config: &Config) -> Result<()> {
+    let (method, params) = match command {
+        CtlCommand::Status => ("status", Value::Null),
+        CtlCommand::Enable { name } => ("plugins.enable", json!({ "name": name })),
+        CtlCommand::Disable { name } => ("plugins.disable", json!({ "name": name })),
+        CtlCommand::Chat { text, notify } => {
+            ("chatbox.send", json!({ "text": text, "notify": notify }))
+        }
+        CtlCommand::Get { name } => ("parameters.get", json!({ "name": name })),
+        CtlCommand::Set { name, value } => {
+            let value = match parse_arg(value) {
+                OscType::Bool(bool) => json!(bool),
+                OscType::Int(int) => json!(int),
+                OscType::Float(float) => json!(float),
+                _ => bail!("Parameters are bools, ints or floats, not {value}"),
+            };
+
+            ("parameters.set", json!({ "name": name, "value": value }))
+        }
+        CtlCommand::Call { method, params } => {
+            let params = params
+                .as_deref()
+                .map(serde_json::from_str)
+                .transpose()?
+                .unwrap_or_default();
+
+            (method.as_str(), params)
+        }
+    };
+
+    let result = control::call(&control::path(config), method, &params)?;
+    if !result.is_null() {
+        println!("{}", serde_json::to_string_pretty(&result)?);
+    }
+
+    Ok(())
+}
+
+fn send(config: &Config, addr: &str, args: &[String]) -> Result<()> {
+    let packet = OscPacket::Message(OscMessage {
+        addr: addr.into(),
+        args: args.iter().map(|arg| parse_arg(arg)).collect(),
+    });
+
+    let buf = rosc::encoder::encode(&packet)?;
+    let socket = UdpSocket::bind("0.0.0.0:0")?; // Dynamic port
+    socket.send_to(&buf, &config.send_addr)?;
+
+    Ok(())
+}
+
+fn monitor(config: &Config) -> Result<()> {
+    let socket = UdpSocket::bind(&config.bind_addr)?;
+    println!("Listening on {}", socket.local_addr()?);
+
+    let mut buf = vec![0u8; u16::MAX.into()];
+    loop {
+        let size = socket.recv(&mut buf)?;
+        match rosc::decoder::decode_udp(&buf[..size]) {
+            Ok((_buf, packet)) => print_packet(packet),
+            Err(error) => eprintln!("Invalid packet: {error}"),
+        }
+    }
+}
+
+fn print_packet(packet: OscPacket) {
+    match packet {
+        OscPacket::Message(message) => println!("{} | {:?}", message.addr, message.args),
+        OscPacket::Bundle(bundle) => bundle.content.into_iter().for_each(print_packet),
+    }
 }
@@ -0,0 +1,295 @@
+//! Counts the traffic of every plugin and address, served in the Prometheus text format
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    net::SocketAddr,
+    sync::{Mutex, PoisonError},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use rosc::{OscMessage, OscType};
+use tiny_http::{Header, Request, Response, Server};
+
+use crate::session::Direction;
+
+/// How many parts of an address are counted together, like `/avatar/parameters` or `/chatbox/input`
+const PREFIX_DEPTH: usize = 2;
+
+/// Upper bounds of the chat latency buckets in seconds
+const CHAT_BUCKETS: [f64; 10] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0];
+
+#[derive(Clone, Copy, Default)]
+struct Traffic {
+    packets: u64,
+    bytes:   u64,
+}
+
+#[derive(Clone, Copy, Default)]
+struct Histogram {
+    /// Cumulative, each bucket counts everything up to its bound
+    buckets: [u64; CHAT_BUCKETS.len()],
+    count:   u64,
+    sum:     f64,
+}
+
+struct Metrics {
+    plugins:    BTreeMap<(String, Direction), Traffic>,
+    addresses:  BTreeMap<(String, Direction), Traffic>,
+    chat:       BTreeMap<String, Histogram>,
+    restarts:   BTreeMap<String, u64>,
+    /// Mismatched parameters by plugin and what happened to them
+    validation: BTreeMap<(String, &'static str), u64>,
+}
+
+static METRICS: Mutex<Metrics> = Mutex::new(Metrics {
+    plugins:    BTreeMap::new(),
+    addresses:  BTreeMap::new(),
+    chat:       BTreeMap::new(),
+    restarts:   BTreeMap::new(),
+    validation: BTreeMap::new(),
+});
+
+fn with_metrics<T>(f: impl FnOnce(&mut Metrics) -> T) -> T {
+    // Counters are still valid if a thread panicked while holding them
+    f(&mut METRICS.lock().unwrap_or_else(PoisonError::into_inner))
+}
+
+/// Counts a packet the plugin sent, or one delivered to it
+pub fn plugin_traffic(plugin: &str, direction: Direction, bytes: usize) {
+    with_metrics(|metrics| {
+        let traffic = metrics
+            .plugins
+            .entry((plugin.to_owned(), direction))
+            .or_default();
+
+        traffic.packets += 1;
+        traffic.bytes += bytes as u64;
+    });
+}
+
+/// Counts a message from or to VRChat by its address prefix
+pub fn address_traffic(message: &OscMessage, direction: Direction) {
+    let prefix = message
+        .addr
+        .split('/')
+        .skip(1)
+        .take(PREFIX_DEPTH)
+        .fold(String::new(), |prefix, part| prefix + "/" + part);
+
+    with_metrics(|metrics| {
+        let traffic = metrics.addresses.entry((prefix, direction)).or_default();
+        traffic.packets += 1;
+        traffic.bytes += encoded_len(message) as u64;
+    });
+}
+
+/// Records how long a chat provider took to change the message
+pub fn chat_latency(provider: &str, latency: Duration) {
+    let seconds = latency.as_secs_f64();
+    with_metrics(|metrics| {
+        let histogram = metrics.chat.entry(provider.to_owned()).or_default();
+        for (bucket, bound) in histogram.buckets.iter_mut().zip(CHAT_BUCKETS) {
+            if seconds <= bound {
+                *bucket += 1;
+            }
+        }
+
+        histogram.count += 1;
+        histogram.sum += seconds;
+    });
+}
+
+pub fn plugin_restarted(plugin: &str) {
+    with_metrics(|metrics| *metrics.restarts.entry(plugin.to_owned()).or_default() += 1);
+}
+
+/// Counts a parameter the plugin sent that didn't match the avatar, `result` is the mismatch or what was
+/// done about it
+pub fn validation(plugin: &str, result: &'static str) {
+    with_metrics(|metrics| {
+        *metrics
+            .validation
+            .entry((plugin.to_owned(), result))
+            .or_default() += 1;
+    });
+}
+
+/// The size of the message once encoded, without encoding it again
+fn encoded_len(message: &OscMessage) -> usize {
+    // The type tags start with a comma
+    let tags = 1 + message.args.len();
+    let args = message.args.iter().map(arg_len).sum::<usize>();
+
+    padded(message.addr.len()) + padded(tags) + args
+}
+
+/// Strings are null terminated and padded to 4 bytes
+const fn padded(len: usize) -> usize {
+    (len + 4) & !3
+}
+
+const fn arg_len(arg: &OscType) -> usize {
+    match arg {
+        OscType::String(string) => padded(string.len()),
+        OscType::Blob(blob) => 4 + blob.len().next_multiple_of(4),
+        OscType::Long(_) | OscType::Double(_) | OscType::Time(_) => 8,
+        OscType::Bool(_) | OscType::Nil | OscType::Inf => 0,
+        _ => 4, // Ints, floats, chars, colors and MIDI, VRChat doesn't send arrays
+    }
+}
+
+/// Label values can't contain unescaped quotes, backslashes or newlines
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', "\\\"")
+        .replace('\n', r"\n")
+}
+
+const fn direction(direction: Direction) -> &'static str {
+    match direction {
+        Direction::Incoming => "incoming",
+        Direction::Outgoing => "outgoing",
+    }
+}
+
+fn header(text: &mut String, name: &str, kind: &str, help: &str) -> std::fmt::Result {
+    writeln!(text, "# HELP vrc_osc_{name} {help}")?;
+    writeln!(text, "# TYPE vrc_osc_{name} {kind}")
+}
+
+fn traffic(
+    text: &mut String,
+    name: &str,
+    label: &str,
+    counters: &BTreeMap<(String, Direction), Traffic>,
+) -> std::fmt::Result {
+    for unit in ["packets", "bytes"] {
+        let help = format!("The {unit} from VRChat (incoming) or to it (outgoing) by {label}");
+        header(text, &format!("{name}_{unit}_total"), "counter", &help)?;
+        for ((key, dir), traffic) in counters {
+            let value = if unit == "bytes" {
+                traffic.bytes
+            } else {
+                traffic.packets
+            };
+
+            let (key, dir) = (escape(key), direction(*dir));
+            writeln!(
+                text,
+                "vrc_osc_{name}_{unit}_total{{{label}=\"{key}\",direction=\"{dir}\"}} {value}"
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Every metric in the Prometheus text format
+#[must_use]
+pub fn render() -> String {
+    with_metrics(|metrics| {
+        let mut text = String::new();
+        let _ = render_into(&mut text, metrics);
+        text
+    })
+}
+
+fn render_into(text: &mut String, metrics: &Metrics) -> std::fmt::Result {
+    traffic(text, "plugin", "plugin", &metrics.plugins)?;
+    traffic(text, "address", "prefix", &metrics.addresses)?;
+
+    let name = "chat_provider_duration_seconds";
+    header(text, name, "histogram", "How long each chat provider took")?;
+    for (provider, histogram) in &metrics.chat {
+        let provider = escape(provider);
+        for (count, bound) in histogram.buckets.iter().zip(CHAT_BUCKETS) {
+            writeln!(
+                text,
+                "vrc_osc_{name}_bucket{{provider=\"{provider}\",le=\"{bound}\"}} {count}"
+            )?;
+        }
+
+        let Histogram { count, sum, .. } = histogram;
+        writeln!(
+            text,
+            "vrc_osc_{name}_bucket{{provider=\"{provider}\",le=\"+Inf\"}} {count}"
+        )?;
+        writeln!(text, "vrc_osc_{name}_sum{{provider=\"{provider}\"}} {sum}")?;
+        writeln!(
+            text,
+            "vrc_osc_{name}_count{{provider=\"{provider}\"}} {count}"
+        )?;
+    }
+
+    let name = "plugin_restarts_total";
+    header(
+        text,
+        name,
+        "counter",
+        "How many times each plugin was restarted",
+    )?;
+    for (plugin, restarts) in &metrics.restarts {
+        writeln!(
+            text,
+            "vrc_osc_{name}{{plugin=\"{}\"}} {restarts}",
+            escape(plugin)
+        )?;
+    }
+
+    let name = "validation_total";
+    header(
+        text,
+        name,
+        "counter",
+        "Parameters each plugin sent that didn't match the avatar: missing or wrong-type, then dropped or coerced",
+    )?;
+    for ((plugin, result), count) in &metrics.validation {
+        writeln!(
+            text,
+            "vrc_osc_{name}{{plugin=\"{}\",result=\"{result}\"}} {count}",
+            escape(plugin)
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Serves the metrics on `/metrics` from a background thread, returns the address it's listening on
+///
+/// # Errors
+///
+/// Will return `Err` if couldn't bind the address
+pub fn serve(addr: &str) -> Result<SocketAddr> {
+    let server = Server::http(addr).map_err(|error| anyhow!("Failed to bind {addr}: {error}"))?;
+    let addr = server
+        .server_addr()
+        .to_ip()
+        .ok_or_else(|| anyhow!("The metrics server isn't listening on an IP address"))?;
+
+    std::thread::spawn(move || {
+        for request in server.incoming_requests() {
+            if let Err(error) = respond(request) {
+                tracing::error!("Metrics: {error}");
+            }
+        }
+    });
+
+    Ok(addr)
+}
+
+fn respond(request: Request) -> Result<()> {
+    if request.url() != "/metrics" {
+        request.respond(Response::empty(404))?;
+        return Ok(());
+    }
+
+    let header = Header::from_bytes("Content-Type", "text/plain; version=0.0.4")
+        .map_err(|()| anyhow!("Invalid header"))?;
+
+    request.respond(Response::from_string(render()).with_header(header))?;
+
+    Ok(())
+}
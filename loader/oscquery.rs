@@ -0,0 +1,309 @@
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail, Result};
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tiny_http::{Header, Request, Response, Server};
+
+use crate::{
+    avatar::{self, Avatar, Parameter, ParameterType, AVATAR_CHANGE, PARAMETERS_PREFIX},
+    pattern::Pattern,
+    RunningPlugins,
+};
+
+const OSCJSON_SERVICE: &str = "_oscjson._tcp.local.";
+const OSC_SERVICE: &str = "_osc._udp.local.";
+const VRCHAT_INSTANCE: &str = "VRChat-Client-";
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Serves the OSCQuery tree over HTTP and advertises it over mDNS until dropped
+pub struct OscQuery {
+    daemon:        ServiceDaemon,
+    pub http_port: u16,
+}
+
+impl OscQuery {
+    /// Starts the HTTP server on a dynamic port and registers both services
+    ///
+    /// The tree has every running plugin's subscriptions and the forwarded patterns
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if couldn't start the HTTP server or register the services
+    pub fn start(
+        osc_addr: SocketAddr,
+        plugins: RunningPlugins,
+        forwarded: Vec<Pattern>,
+    ) -> Result<Self> {
+        let server = Server::http("0.0.0.0:0").map_err(|error| anyhow!(error))?;
+        let http_port = server
+            .server_addr()
+            .to_ip()
+            .map(|addr| addr.port())
+            .ok_or_else(|| anyhow!("The OSCQuery server isn't listening on an IP address"))?;
+
+        // VRChat connects to us locally, 0.0.0.0 isn't a valid destination
+        let osc_ip = if osc_addr.ip().is_unspecified() {
+            "127.0.0.1".to_owned()
+        } else {
+            osc_addr.ip().to_string()
+        };
+
+        let host_info = json!({
+            "NAME": format!("VRC-OSC-{}", osc_addr.port()),
+            "OSC_IP": osc_ip,
+            "OSC_PORT": osc_addr.port(),
+            "OSC_TRANSPORT": "UDP",
+            "EXTENSIONS": {
+                "ACCESS": true,
+                "VALUE": false,
+            },
+        });
+
+        std::thread::spawn(move || {
+            for request in server.incoming_requests() {
+                if let Err(error) = respond(request, &host_info, &plugins, &forwarded) {
+                    tracing::error!("OSCQuery: {error}");
+                }
+            }
+        });
+
+        let daemon = ServiceDaemon::new()?;
+        let instance = format!("VRC-OSC-{}", osc_addr.port());
+        let hostname = format!("{instance}.local.");
+        for (service, port) in [(OSCJSON_SERVICE, http_port), (OSC_SERVICE, osc_addr.port())] {
+            let properties: [(&str, &str); 0] = [];
+            let info = ServiceInfo::new(service, &instance, &hostname, "", port, &properties[..])?
+                .enable_addr_auto();
+
+            daemon.register(info)?;
+        }
+
+        Ok(Self { daemon, http_port })
+    }
+}
+
+impl Drop for OscQuery {
+    fn drop(&mut self) {
+        let _ = self.daemon.shutdown();
+    }
+}
+
+/// A node in the OSCQuery address tree
+#[derive(Default)]
+struct Node {
+    /// Whether VRChat can send to this address, otherwise it's only a container
+    writable: bool,
+    contents: BTreeMap<String, Self>,
+}
+
+impl Node {
+    fn insert(&mut self, path: &str) {
+        let node = path
+            .split('/')
+            .filter(|part| !part.is_empty())
+            .fold(self, |node, part| {
+                node.contents.entry(part.to_owned()).or_default()
+            });
+
+        node.writable = true;
+    }
+
+    fn get(&self, path: &str) -> Option<&Self> {
+        path.split('/')
+            .filter(|part| !part.is_empty())
+            .try_fold(self, |node, part| node.contents.get(part))
+    }
+
+    fn to_json(&self, full_path: &str) -> Value {
+        let contents = self
+            .contents
+            .iter()
+            .map(|(name, node)| {
+                let path = format!("{}/{name}", full_path.trim_end_matches('/'));
+                (name.clone(), node.to_json(&path))
+            })
+            .collect::<serde_json::Map<_, _>>();
+
+        let mut json = json!({
+            "FULL_PATH": full_path,
+            "ACCESS": if self.writable { 2 } else { 0 },
+        });
+
+        if !contents.is_empty() {
+            json["CONTENTS"] = Value::Object(contents);
+        }
+
+        json
+    }
+}
+
+/// Builds the tree from the literal part of every running plugin's subscriptions and forwarded pattern
+fn tree(plugins: &RunningPlugins, forwarded: &[Pattern]) -> Node {
+    let mut root = Node::default();
+    root.insert(AVATAR_CHANGE); // The loader always needs to know when the avatar changes
+
+    for pattern in forwarded {
+        root.insert(&pattern.prefix());
+    }
+
+    let plugins = plugins.read().expect("Failed to read plugins").clone();
+    for plugin in &plugins {
+        for subscription in &plugin.plugin.subscriptions {
+            root.insert(&subscription.prefix());
+        }
+    }
+
+    root
+}
+
+fn respond(
+    request: Request,
+    host_info: &Value,
+    plugins: &RunningPlugins,
+    forwarded: &[Pattern],
+) -> Result<()> {
+    let url = request.url().to_owned();
+    let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+
+    let body = if query == "HOST_INFO" {
+        host_info.clone()
+    } else if let Some(node) = tree(plugins, forwarded).get(path) {
+        node.to_json(path)
+    } else {
+        request.respond(Response::empty(404))?;
+        return Ok(());
+    };
+
+    let header = Header::from_bytes("Content-Type", "application/json")
+        .map_err(|()| anyhow!("Invalid header"))?;
+
+    request.respond(Response::from_string(body.to_string()).with_header(header))?;
+
+    Ok(())
+}
+
+/// A node in another application's OSCQuery tree
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+struct RemoteNode {
+    full_path: String,
+    #[serde(rename = "TYPE")]
+    kind:      Option<String>,
+    value:     Option<Vec<Value>>,
+    #[serde(default)]
+    contents:  BTreeMap<String, Self>,
+}
+
+impl RemoteNode {
+    fn parameters(&self, parameters: &mut Vec<Parameter>) {
+        let kind = self.kind.as_deref().and_then(ParameterType::from_type_tag);
+        let name = self.full_path.strip_prefix(PARAMETERS_PREFIX);
+        if let (Some(kind), Some(name)) = (kind, name) {
+            parameters.push(Parameter {
+                name: name.to_owned(),
+                kind,
+            });
+        }
+
+        for node in self.contents.values() {
+            node.parameters(parameters);
+        }
+    }
+}
+
+/// Browses mDNS for VRChat's OSCQuery service and returns its HTTP URL
+///
+/// # Errors
+///
+/// Will return `Err` if couldn't browse mDNS or VRChat wasn't found within the timeout
+pub fn find_vrchat(timeout: Duration) -> Result<String> {
+    let daemon = ServiceDaemon::new()?;
+    let receiver = daemon.browse(OSCJSON_SERVICE)?;
+    let deadline = Instant::now() + timeout;
+
+    let url = loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let Ok(event) = receiver.recv_timeout(remaining) else {
+            break None;
+        };
+
+        let ServiceEvent::ServiceResolved(info) = event else {
+            continue;
+        };
+
+        if !info.get_fullname().starts_with(VRCHAT_INSTANCE) {
+            continue; // Another OSC application
+        }
+
+        if let Some(ip) = info.get_addresses().iter().next() {
+            break Some(format!("http://{ip}:{}", info.get_port()));
+        }
+    };
+
+    let _ = daemon.shutdown();
+    url.ok_or_else(|| anyhow!("Couldn't find VRChat's OSCQuery service"))
+}
+
+/// Fetches the current avatar and its parameters from VRChat's OSCQuery service
+///
+/// # Errors
+///
+/// Will return `Err` if the request failed or the response isn't an OSCQuery tree
+pub fn fetch_avatar(url: &str) -> Result<Avatar> {
+    let response = ureq::get(&format!("{}/avatar", url.trim_end_matches('/'))).call()?;
+    let root = serde_json::from_str::<RemoteNode>(&response.into_string()?)?;
+    if root.full_path != "/avatar" {
+        bail!("{url} didn't return the avatar node");
+    }
+
+    let id = root
+        .contents
+        .get("change")
+        .and_then(|node| node.value.as_ref()?.first()?.as_str())
+        .map(ToOwned::to_owned);
+
+    let mut parameters = Vec::new();
+    root.parameters(&mut parameters);
+
+    Ok(Avatar { id, parameters })
+}
+
+/// Keeps [`avatar::current`] up to date, fetching it again whenever the avatar changes
+///
+/// VRChat is discovered over mDNS unless a URL is configured, and again if it stops responding
+pub fn track_avatar(configured_url: Option<String>) {
+    tokio::spawn(async move {
+        let mut url = configured_url.clone();
+        loop {
+            if url.is_none() {
+                match tokio::task::spawn_blocking(|| find_vrchat(DISCOVERY_TIMEOUT)).await {
+                    Ok(Ok(found)) => url = Some(found),
+                    Ok(Err(_)) | Err(_) => continue, // VRChat isn't running yet
+                }
+            }
+
+            let Some(current_url) = url.clone() else {
+                continue;
+            };
+
+            match tokio::task::spawn_blocking(move || fetch_avatar(&current_url)).await {
+                Ok(Ok(avatar)) => avatar::set_current(avatar),
+                Ok(Err(error)) => {
+                    tracing::error!("OSCQuery: {error:#}");
+                    url.clone_from(&configured_url); // VRChat may have restarted on another port
+                    tokio::time::sleep(DISCOVERY_TIMEOUT).await;
+                    continue;
+                }
+                Err(error) => tracing::error!("OSCQuery: {error}"),
+            }
+
+            avatar::changed().await;
+        }
+    });
+}
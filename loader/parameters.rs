@@ -0,0 +1,159 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Condvar, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use rosc::{OscMessage, OscType};
+use serde::{Deserialize, Serialize};
+
+use crate::avatar::PARAMETERS_PREFIX;
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+pub enum ParameterValue {
+    Bool(bool),
+    Int(i32),
+    Float(f32),
+}
+
+impl ParameterValue {
+    /// Returns `None` for OSC types avatar parameters can't have
+    #[must_use]
+    pub const fn from_osc(arg: &OscType) -> Option<Self> {
+        match arg {
+            OscType::Bool(bool) => Some(Self::Bool(*bool)),
+            OscType::Int(int) => Some(Self::Int(*int)),
+            OscType::Float(float) => Some(Self::Float(*float)),
+            _ => None,
+        }
+    }
+}
+
+impl From<ParameterValue> for OscType {
+    fn from(value: ParameterValue) -> Self {
+        match value {
+            ParameterValue::Bool(bool) => Self::Bool(bool),
+            ParameterValue::Int(int) => Self::Int(int),
+            ParameterValue::Float(float) => Self::Float(float),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+pub struct CachedParameter {
+    pub value:   ParameterValue,
+    /// Milliseconds since the Unix epoch when the value was last routed
+    pub updated: u64,
+}
+
+/// Parameters that changed after a sequence number, see [`wait_changes`]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Changes {
+    pub seq:     u64,
+    pub changes: Vec<(String, CachedParameter)>,
+}
+
+struct Cache {
+    /// Bumped whenever a value changes
+    seq:     u64,
+    entries: BTreeMap<String, (u64, CachedParameter)>,
+}
+
+static CACHE: Mutex<Cache> = Mutex::new(Cache {
+    seq:     0,
+    entries: BTreeMap::new(),
+});
+static CHANGED: Condvar = Condvar::new();
+
+fn lock() -> std::sync::MutexGuard<'static, Cache> {
+    CACHE.lock().expect("Failed to lock the parameter cache")
+}
+
+/// Remembers the value of a routed parameter, other messages are ignored
+#[allow(clippy::cast_possible_truncation)]
+pub fn record(message: &OscMessage) {
+    let Some(name) = message.addr.strip_prefix(PARAMETERS_PREFIX) else {
+        return; // Not a parameter
+    };
+
+    let Some(value) = message.args.first().and_then(ParameterValue::from_osc) else {
+        return; // Not a parameter value
+    };
+
+    let updated = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+
+    let mut cache = lock();
+    let previous = cache.entries.get(name).copied();
+    let changed = previous.is_none_or(|(_, parameter)| parameter.value != value);
+    if changed {
+        cache.seq += 1;
+    }
+
+    // Only value changes are reported to subscribers
+    let seq = match previous {
+        Some((seq, _)) if !changed => seq,
+        _ => cache.seq,
+    };
+
+    let parameter = CachedParameter { value, updated };
+    cache.entries.insert(name.to_owned(), (seq, parameter));
+    drop(cache);
+
+    if changed {
+        CHANGED.notify_all();
+    }
+}
+
+/// Forgets every value, called when the avatar changes
+pub fn reset() {
+    let mut cache = lock();
+    cache.entries.clear();
+    cache.seq += 1;
+    drop(cache);
+
+    CHANGED.notify_all();
+}
+
+/// The last known value of a parameter, by its name after `/avatar/parameters/`
+#[must_use]
+pub fn get(name: &str) -> Option<CachedParameter> {
+    lock().entries.get(name).map(|(_, parameter)| *parameter)
+}
+
+#[must_use]
+pub fn snapshot() -> BTreeMap<String, CachedParameter> {
+    lock()
+        .entries
+        .iter()
+        .map(|(name, (_, parameter))| (name.clone(), *parameter))
+        .collect()
+}
+
+/// Blocks until a parameter changes after `after` or the timeout passes
+///
+/// Pass `u64::MAX` with no timeout to get the current sequence number
+///
+/// # Panics
+///
+/// Will panic if the cache lock was poisoned
+#[must_use]
+pub fn wait_changes(after: u64, timeout: Duration) -> Changes {
+    let (cache, _) = CHANGED
+        .wait_timeout_while(lock(), timeout, |cache| cache.seq <= after)
+        .expect("Failed to lock the parameter cache");
+
+    let changes = cache
+        .entries
+        .iter()
+        .filter(|(_, (seq, _))| *seq > after)
+        .map(|(name, (_, parameter))| (name.clone(), *parameter))
+        .collect();
+
+    Changes {
+        seq: cache.seq,
+        changes,
+    }
+}
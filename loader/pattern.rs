@@ -0,0 +1,401 @@
+use std::{
+    fmt::{Display, Formatter},
+    ops::Range,
+    str::FromStr,
+};
+
+use anyhow::{bail, Context, Error, Result};
+
+/// An OSC 1.0 address pattern with OSC 1.1 `//` path traversal
+///
+/// ```ignore
+/// let media = Pattern::new("/avatar/parameters/VRCOSC/Media/*")?;
+/// assert_eq!(media.captures("/avatar/parameters/VRCOSC/Media/Play"), Some(vec!["Play"]));
+/// ```
+#[derive(Clone, Debug)]
+pub struct Pattern {
+    pattern: String,
+    parts:   Vec<Part>,
+}
+
+#[derive(Clone, Debug)]
+enum Part {
+    /// `//`, matches any number of parts
+    Traverse,
+    Tokens(Vec<Token>),
+}
+
+#[derive(Clone, Debug)]
+enum Token {
+    Char(char),
+    /// `?`
+    Any,
+    /// `*`
+    Wildcard,
+    /// `[a-z]` or `[!a-z]`
+    Class {
+        negated: bool,
+        ranges:  Vec<(char, char)>,
+    },
+    /// `{foo,bar}`
+    Alternatives(Vec<String>),
+}
+
+impl Part {
+    fn parse(part: &str) -> Result<Self> {
+        let mut tokens = Vec::new();
+        let mut chars = part.chars().peekable();
+        while let Some(char) = chars.next() {
+            let token = match char {
+                '?' => Token::Any,
+                '*' => {
+                    if matches!(tokens.last(), Some(Token::Wildcard)) {
+                        continue; // Consecutive wildcards are the same as one
+                    }
+
+                    Token::Wildcard
+                }
+                '[' => {
+                    let negated = chars.next_if_eq(&'!').is_some();
+                    let mut ranges = Vec::new();
+                    loop {
+                        let start = chars.next().context("Unclosed [")?;
+                        if start == ']' {
+                            break;
+                        }
+
+                        // A trailing - is a literal
+                        let end = if chars.peek() == Some(&'-') {
+                            chars.next();
+                            match chars.next().context("Unclosed [")? {
+                                ']' => {
+                                    ranges.extend([(start, start), ('-', '-')]);
+                                    break;
+                                }
+                                end => end,
+                            }
+                        } else {
+                            start
+                        };
+
+                        ranges.push((start.min(end), start.max(end)));
+                    }
+
+                    Token::Class { negated, ranges }
+                }
+                '{' => {
+                    let mut alternatives = String::new();
+                    loop {
+                        match chars.next().context("Unclosed {")? {
+                            '}' => break,
+                            '{' | '[' | '*' | '?' => {
+                                bail!("Nested patterns aren't supported in {{}}")
+                            }
+                            char => alternatives.push(char),
+                        }
+                    }
+
+                    Token::Alternatives(alternatives.split(',').map(String::from).collect())
+                }
+                ']' | '}' => bail!("Unopened {char}"),
+                ' ' | '#' | ',' => bail!("{char:?} isn't allowed in an OSC address"),
+                char => Token::Char(char),
+            };
+
+            tokens.push(token);
+        }
+
+        Ok(Self::Tokens(tokens))
+    }
+}
+
+impl Token {
+    /// Returns what's left of the part after this token, for every way it can match
+    fn matches<'a>(&'a self, part: &'a str) -> Box<dyn Iterator<Item = &'a str> + 'a> {
+        let mut chars = part.chars();
+        let first = chars.next();
+        let rest = chars.as_str();
+
+        match self {
+            Self::Char(char) => Box::new((first == Some(*char)).then_some(rest).into_iter()),
+            Self::Any => Box::new(first.map(|_| rest).into_iter()),
+            Self::Wildcard => Box::new(
+                part.char_indices()
+                    .map(|(index, _)| &part[index..])
+                    .chain([""]),
+            ),
+            Self::Class { negated, ranges } => {
+                let matched = first.is_some_and(|char| {
+                    ranges
+                        .iter()
+                        .any(|(start, end)| (*start..=*end).contains(&char))
+                        != *negated
+                });
+
+                Box::new(matched.then_some(rest).into_iter())
+            }
+            Self::Alternatives(alternatives) => Box::new(
+                alternatives
+                    .iter()
+                    .filter_map(|alternative| part.strip_prefix(alternative.as_str())),
+            ),
+        }
+    }
+}
+
+fn matches_tokens(tokens: &[Token], part: &str) -> bool {
+    let Some((token, tokens)) = tokens.split_first() else {
+        return part.is_empty();
+    };
+
+    token.matches(part).any(|rest| matches_tokens(tokens, rest))
+}
+
+impl Pattern {
+    /// # Errors
+    ///
+    /// Will return `Err` if the pattern isn't a valid OSC address pattern
+    pub fn new(pattern: &str) -> Result<Self> {
+        Self::parse(pattern).with_context(|| format!("Invalid OSC address pattern {pattern}"))
+    }
+
+    fn parse(pattern: &str) -> Result<Self> {
+        let Some(rest) = pattern.strip_prefix('/') else {
+            bail!("Patterns must start with /");
+        };
+
+        let mut parts = Vec::new();
+        let mut split = rest.split('/').peekable();
+        while let Some(part) = split.next() {
+            if !part.is_empty() {
+                parts.push(Part::parse(part)?);
+            } else if split.peek().is_some_and(|next| !next.is_empty()) {
+                parts.push(Part::Traverse);
+            } else {
+                bail!("Patterns can't have empty parts");
+            }
+        }
+
+        Ok(Self {
+            pattern: pattern.to_owned(),
+            parts,
+        })
+    }
+
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    /// The literal parts before the first wildcard or `//`,
+    /// such as `/avatar/parameters` for `/avatar/parameters/*`, or an empty string
+    #[must_use]
+    pub fn prefix(&self) -> String {
+        self.pattern[1..]
+            .split('/')
+            .take_while(|part| !part.is_empty() && !part.contains(['?', '*', '[', '{']))
+            .fold(String::new(), |prefix, part| prefix + "/" + part)
+    }
+
+    #[must_use]
+    pub fn matches(&self, addr: &str) -> bool {
+        self.captures(addr).is_some()
+    }
+
+    /// Returns the address parts matched by each part of the pattern that isn't a literal,
+    /// with everything a `//` skipped over as one capture
+    #[must_use]
+    pub fn captures<'a>(&self, addr: &'a str) -> Option<Vec<&'a str>> {
+        let rest = addr.strip_prefix('/')?;
+
+        let mut ranges = Vec::new();
+        let mut start = 1;
+        for part in rest.split('/') {
+            ranges.push(start..start + part.len());
+            start += part.len() + 1;
+        }
+
+        let mut captures = Vec::new();
+        Matcher { addr, ranges }
+            .matches(&self.parts, 0, &mut captures)
+            .then_some(captures)
+    }
+}
+
+struct Matcher<'a> {
+    addr:   &'a str,
+    ranges: Vec<Range<usize>>,
+}
+
+impl<'a> Matcher<'a> {
+    fn matches(&self, parts: &[Part], index: usize, captures: &mut Vec<&'a str>) -> bool {
+        let Some((part, parts)) = parts.split_first() else {
+            return index == self.ranges.len();
+        };
+
+        let previous = captures.len();
+        match part {
+            Part::Traverse => {
+                for end in index..=self.ranges.len() {
+                    let skipped = if end > index {
+                        &self.addr[self.ranges[index].start..self.ranges[end - 1].end]
+                    } else {
+                        ""
+                    };
+
+                    captures.push(skipped);
+                    if self.matches(parts, end, captures) {
+                        return true;
+                    }
+
+                    captures.truncate(previous);
+                }
+
+                false
+            }
+            Part::Tokens(tokens) => {
+                let Some(range) = self.ranges.get(index) else {
+                    return false;
+                };
+
+                let part = &self.addr[range.clone()];
+                if !matches_tokens(tokens, part) {
+                    return false;
+                }
+
+                if !tokens.iter().all(|token| matches!(token, Token::Char(_))) {
+                    captures.push(part);
+                }
+
+                if self.matches(parts, index + 1, captures) {
+                    return true;
+                }
+
+                captures.truncate(previous);
+                false
+            }
+        }
+    }
+}
+
+impl FromStr for Pattern {
+    type Err = Error;
+
+    fn from_str(pattern: &str) -> Result<Self> {
+        Self::new(pattern)
+    }
+}
+
+impl Display for Pattern {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        f.write_str(&self.pattern)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(pattern: &str) -> Pattern {
+        Pattern::new(pattern).unwrap()
+    }
+
+    #[test]
+    fn question_mark_matches_one_char() {
+        let pattern = parse("/input/Jump?");
+        assert!(pattern.matches("/input/Jump1"));
+        assert!(!pattern.matches("/input/Jump"));
+        assert!(!pattern.matches("/input/Jump12"));
+    }
+
+    #[test]
+    fn wildcard_matches_within_a_part() {
+        let pattern = parse("/avatar/parameters/VRCOSC/Media/*");
+        assert!(pattern.matches("/avatar/parameters/VRCOSC/Media/Play"));
+        assert!(pattern.matches("/avatar/parameters/VRCOSC/Media/"));
+        assert!(!pattern.matches("/avatar/parameters/VRCOSC/Media/Play/Now"));
+        assert!(parse("/input/*Axis").matches("/input/LookHorizontalAxis"));
+        assert!(parse("/input/**").matches("/input/Jump"));
+    }
+
+    #[test]
+    fn classes_match_ranges() {
+        let pattern = parse("/input/Jump[0-9a]");
+        assert!(pattern.matches("/input/Jump5"));
+        assert!(pattern.matches("/input/Jumpa"));
+        assert!(!pattern.matches("/input/Jumpb"));
+
+        // Ranges can be backwards and a trailing - is a literal
+        assert!(parse("/[9-0]").matches("/5"));
+        assert!(parse("/[a-]").matches("/-"));
+    }
+
+    #[test]
+    fn negated_classes_match_everything_else() {
+        let pattern = parse("/input/Jump[!0-9]");
+        assert!(pattern.matches("/input/Jumpa"));
+        assert!(!pattern.matches("/input/Jump5"));
+        assert!(!pattern.matches("/input/Jump"));
+    }
+
+    #[test]
+    fn alternatives_match_any_of_them() {
+        let pattern = parse("/avatar/parameters/VRCOSC/Clock/{Hours,Minutes}");
+        assert!(pattern.matches("/avatar/parameters/VRCOSC/Clock/Hours"));
+        assert!(pattern.matches("/avatar/parameters/VRCOSC/Clock/Minutes"));
+        assert!(!pattern.matches("/avatar/parameters/VRCOSC/Clock/Seconds"));
+        assert!(parse("/{Look,Move}*").matches("/MoveForward"));
+    }
+
+    #[test]
+    fn traversal_matches_any_number_of_parts() {
+        let pattern = parse("/avatar//Play");
+        assert!(pattern.matches("/avatar/Play"));
+        assert!(pattern.matches("/avatar/parameters/VRCOSC/Media/Play"));
+        assert!(!pattern.matches("/avatar/parameters/Pause"));
+        assert!(parse("//*").matches("/chatbox/input"));
+    }
+
+    #[test]
+    fn captures_every_part_that_isnt_a_literal() {
+        let pattern = parse("/avatar//{Media,Clock}/*");
+        assert_eq!(
+            pattern.captures("/avatar/parameters/VRCOSC/Media/Play"),
+            Some(vec!["parameters/VRCOSC", "Media", "Play"])
+        );
+        assert_eq!(
+            pattern.captures("/avatar/Clock/Hours"),
+            Some(vec!["", "Clock", "Hours"])
+        );
+        assert_eq!(pattern.captures("/avatar/parameters/Play"), None);
+        assert_eq!(
+            parse("/chatbox/input").captures("/chatbox/input"),
+            Some(vec![])
+        );
+    }
+
+    #[test]
+    fn prefix_stops_at_the_first_wildcard() {
+        assert_eq!(parse("/avatar/parameters/*").prefix(), "/avatar/parameters");
+        assert_eq!(parse("/avatar//Play").prefix(), "/avatar");
+        assert_eq!(parse("//*").prefix(), "");
+    }
+
+    #[test]
+    fn rejects_malformed_patterns() {
+        for malformed in [
+            "avatar/parameters",
+            "/input/Jump[0-9",
+            "/input/Jump[0-",
+            "/input/{Jump,Run",
+            "/input/{Jump,[Run]}",
+            "/input/Jump]",
+            "/input/Jump}",
+            "/input/Jump Run",
+            "/input//",
+            "/input/",
+        ] {
+            assert!(Pattern::new(malformed).is_err(), "{malformed} was accepted");
+        }
+    }
+}
@@ -0,0 +1,843 @@
+#[cfg(unix)]
+use std::os::fd::{FromRawFd, IntoRawFd};
+#[cfg(windows)]
+use std::os::windows::io::{FromRawSocket, IntoRawSocket};
+use std::{
+    any::Any,
+    fmt::{Display, Formatter},
+    future::Future,
+    net::UdpSocket,
+    panic::AssertUnwindSafe,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Mutex,
+        PoisonError,
+    },
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use async_ffi::{FfiFuture, FutureExt};
+use derive_config::DeriveTomlConfig;
+use libloading::Library;
+use tokio::{
+    runtime::{Handle, Runtime},
+    sync::Notify,
+};
+
+use crate::{
+    host::{HostVTable, HOST_VTABLE},
+    pattern::Pattern,
+    ChatMessage,
+    CARGO_PKG_VERSION,
+};
+
+/// Bump whenever the layout of [`PluginVTable`] or anything it references changes
+pub const API_VERSION: u32 = 2;
+
+/// A borrowed UTF-8 string, only valid for the duration of the call it was passed to
+/// or for the lifetime of the library when it points to static data
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct RawStr {
+    ptr: *const u8,
+    len: usize,
+}
+
+unsafe impl Send for RawStr {}
+unsafe impl Sync for RawStr {}
+
+impl RawStr {
+    #[must_use]
+    pub const fn new(string: &str) -> Self {
+        Self {
+            ptr: string.as_ptr(),
+            len: string.len(),
+        }
+    }
+
+    /// # Safety
+    ///
+    /// The string this was created from must still be alive
+    #[must_use]
+    pub const unsafe fn as_str<'a>(self) -> &'a str {
+        std::str::from_utf8_unchecked(std::slice::from_raw_parts(self.ptr, self.len))
+    }
+}
+
+/// A borrowed list of [`RawStr`], only valid for the lifetime of the library it points into
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct RawStrs {
+    ptr: *const RawStr,
+    len: usize,
+}
+
+unsafe impl Send for RawStrs {}
+unsafe impl Sync for RawStrs {}
+
+impl RawStrs {
+    #[must_use]
+    pub const fn new(strings: &'static [RawStr]) -> Self {
+        Self {
+            ptr: strings.as_ptr(),
+            len: strings.len(),
+        }
+    }
+
+    /// # Safety
+    ///
+    /// The strings this was created from must still be alive
+    #[must_use]
+    pub unsafe fn to_vec(self) -> Vec<String> {
+        std::slice::from_raw_parts(self.ptr, self.len)
+            .iter()
+            .map(|string| string.as_str().to_owned())
+            .collect()
+    }
+}
+
+/// An owned UTF-8 string, it must be freed by the side of the boundary that allocated it
+#[repr(C)]
+pub struct RawString {
+    ptr: *mut u8,
+    len: usize,
+    cap: usize,
+}
+
+unsafe impl Send for RawString {}
+unsafe impl Sync for RawString {}
+
+impl From<String> for RawString {
+    fn from(string: String) -> Self {
+        let mut string = std::mem::ManuallyDrop::new(string);
+        Self {
+            ptr: string.as_mut_ptr(),
+            len: string.len(),
+            cap: string.capacity(),
+        }
+    }
+}
+
+impl RawString {
+    /// # Safety
+    ///
+    /// Must only be called once, on the side of the boundary that allocated it
+    #[must_use]
+    pub unsafe fn into_string(self) -> String {
+        String::from_raw_parts(self.ptr, self.len, self.cap)
+    }
+
+    #[must_use]
+    pub const fn as_str(&self) -> &str {
+        unsafe {
+            RawStr {
+                ptr: self.ptr.cast_const(),
+                len: self.len,
+            }
+            .as_str()
+        }
+    }
+}
+
+#[repr(C, u8)]
+pub enum FfiResult<T> {
+    Ok(T),
+    Err(RawString),
+}
+
+#[repr(C)]
+pub struct RawChatMessage {
+    pub chatbox: RawString,
+    pub console: RawString,
+}
+
+#[cfg(unix)]
+type RawHandle = std::os::fd::RawFd;
+
+#[cfg(windows)]
+type RawHandle = std::os::windows::io::RawSocket;
+
+/// A socket handed over by its OS handle, both sides live in the same process
+#[repr(C)]
+pub struct RawUdpSocket(RawHandle);
+
+#[cfg(unix)]
+impl From<UdpSocket> for RawUdpSocket {
+    fn from(socket: UdpSocket) -> Self {
+        Self(socket.into_raw_fd())
+    }
+}
+
+#[cfg(windows)]
+impl From<UdpSocket> for RawUdpSocket {
+    fn from(socket: UdpSocket) -> Self {
+        Self(socket.into_raw_socket())
+    }
+}
+
+impl RawUdpSocket {
+    /// # Safety
+    ///
+    /// Must only be called once, the handle must come from [`RawUdpSocket::from`]
+    #[must_use]
+    pub unsafe fn into_socket(self) -> UdpSocket {
+        #[cfg(unix)]
+        let socket = UdpSocket::from_raw_fd(self.0);
+
+        #[cfg(windows)]
+        let socket = UdpSocket::from_raw_socket(self.0);
+
+        socket
+    }
+}
+
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Platforms(u32);
+
+impl Platforms {
+    pub const WINDOWS: Self = Self(1 << 0);
+    pub const LINUX: Self = Self(1 << 1);
+    pub const MACOS: Self = Self(1 << 2);
+    pub const ALL: Self = Self::WINDOWS.union(Self::LINUX).union(Self::MACOS);
+
+    #[must_use]
+    pub const fn current() -> Self {
+        if cfg!(target_os = "windows") {
+            Self::WINDOWS
+        } else if cfg!(target_os = "macos") {
+            Self::MACOS
+        } else {
+            Self::LINUX
+        }
+    }
+
+    #[must_use]
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    #[must_use]
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Capabilities(u32);
+
+impl Capabilities {
+    pub const NONE: Self = Self(0);
+    /// Rewrites chatbox messages through `chat`
+    pub const CHAT: Self = Self(1 << 0);
+    /// Reads incoming OSC packets
+    pub const OSC_CONSUMER: Self = Self(1 << 1);
+    /// Sends outgoing OSC packets
+    pub const OSC_PRODUCER: Self = Self(1 << 2);
+
+    #[must_use]
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    #[must_use]
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+/// Points to static strings inside the plugin library
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct RawMetadata {
+    pub name:           RawStr,
+    pub version:        RawStr,
+    pub description:    RawStr,
+    pub authors:        RawStr,
+    pub loader_version: RawStr,
+    pub platforms:      Platforms,
+    pub capabilities:   Capabilities,
+    pub subscriptions:  RawStrs,
+}
+
+#[derive(Clone, Debug)]
+pub struct Metadata {
+    pub name:           String,
+    pub version:        String,
+    pub description:    String,
+    pub authors:        String,
+    /// The oldest loader the plugin works with, empty for any
+    pub loader_version: String,
+    pub platforms:      Platforms,
+    pub capabilities:   Capabilities,
+    /// OSC address patterns the plugin wants to receive
+    pub subscriptions:  Vec<String>,
+}
+
+impl Metadata {
+    /// # Safety
+    ///
+    /// The library the metadata came from must still be loaded
+    unsafe fn from_raw(raw: RawMetadata) -> Self {
+        Self {
+            name:           raw.name.as_str().to_owned(),
+            version:        raw.version.as_str().to_owned(),
+            description:    raw.description.as_str().to_owned(),
+            authors:        raw.authors.as_str().to_owned(),
+            loader_version: raw.loader_version.as_str().to_owned(),
+            platforms:      raw.platforms,
+            capabilities:   raw.capabilities,
+            subscriptions:  raw.subscriptions.to_vec(),
+        }
+    }
+}
+
+pub type LoadFn =
+    unsafe extern "C" fn(host: &'static HostVTable, socket: RawUdpSocket) -> FfiResult<()>;
+pub type ChatFn =
+    unsafe extern "C" fn(chatbox: RawStr, console: RawStr) -> FfiFuture<FfiResult<RawChatMessage>>;
+/// Returns `false` if chats were still running when it gave up waiting for them
+pub type UnloadFn = unsafe extern "C" fn() -> bool;
+pub type GetConfigFn = unsafe extern "C" fn() -> FfiResult<RawString>;
+pub type SetConfigFn = unsafe extern "C" fn(config: RawStr) -> FfiResult<()>;
+pub type FreeStringFn = unsafe extern "C" fn(string: RawString);
+
+/// Exported by every plugin as `VRC_OSC_PLUGIN`, next to `VRC_OSC_API_VERSION`
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct PluginVTable {
+    pub metadata:    RawMetadata,
+    pub load:        LoadFn,
+    pub chat:        Option<ChatFn>,
+    pub unload:      UnloadFn,
+    /// The config file as JSON, see [`Plugin::config`]
+    pub get_config:  Option<GetConfigFn>,
+    pub set_config:  Option<SetConfigFn>,
+    pub free_string: FreeStringFn,
+}
+
+/// Exports the plugin symbols the loader looks for
+///
+/// ```ignore
+/// loader::export_plugin! {
+///     name: "Spotify",
+///     platforms: Platforms::ALL,
+///     capabilities: Capabilities::CHAT.union(Capabilities::OSC_CONSUMER),
+///     subscriptions: ["/avatar/change", "/avatar/parameters/VRCOSC/Media/*"],
+///     config: Config,
+///     load: load,
+///     chat: chat,
+///     unload: unload,
+/// }
+/// ```
+///
+/// `platforms` defaults to all platforms, `subscriptions` defaults to receiving nothing, the version, description and authors come from Cargo,
+/// `config` is the plugin's `DeriveTomlConfig` struct, letting the loader's dashboard edit it,
+/// `load` is a `fn(UdpSocket) -> anyhow::Result<()>` that may block for the lifetime of the plugin,
+/// `chat` is an `async fn(String, String) -> anyhow::Result<ChatMessage>` run on the plugin's runtime,
+/// `unload` is an optional `fn()` for cleanup like saving state, blocking plugins should return once [`unloading`]
+///
+/// The loader closes the library once `load` returned, so threads the plugin spawned must have exited by then
+#[macro_export]
+macro_rules! export_plugin {
+    (
+        name: $name:literal,
+        $(loader_version: $loader_version:literal,)?
+        $(platforms: $platforms:expr,)?
+        capabilities: $capabilities:expr,
+        $(subscriptions: [$($subscription:literal),* $(,)?],)?
+        $(config: $config:ty,)?
+        load: $load:path
+        $(, chat: $chat:path)?
+        $(, unload: $unload:path)?
+        $(,)?
+    ) => {
+        #[no_mangle]
+        pub static VRC_OSC_API_VERSION: u32 = $crate::plugin::API_VERSION;
+
+        #[no_mangle]
+        pub static VRC_OSC_PLUGIN: $crate::plugin::PluginVTable = $crate::plugin::PluginVTable {
+            metadata:    $crate::plugin::RawMetadata {
+                name:           $crate::plugin::RawStr::new($name),
+                version:        $crate::plugin::RawStr::new(env!("CARGO_PKG_VERSION")),
+                description:    $crate::plugin::RawStr::new(env!("CARGO_PKG_DESCRIPTION")),
+                authors:        $crate::plugin::RawStr::new(env!("CARGO_PKG_AUTHORS")),
+                loader_version: $crate::plugin::RawStr::new(
+                    $crate::export_plugin!(@loader_version $($loader_version)?),
+                ),
+                platforms:      $crate::export_plugin!(@platforms $($platforms)?),
+                capabilities:   {
+                    use $crate::plugin::Capabilities;
+                    $capabilities
+                },
+                subscriptions:  {
+                    const SUBSCRIPTIONS: &[$crate::plugin::RawStr] =
+                        &[$($($crate::plugin::RawStr::new($subscription)),*)?];
+
+                    $crate::plugin::RawStrs::new(SUBSCRIPTIONS)
+                },
+            },
+            load:        {
+                unsafe extern "C" fn vrc_osc_load(
+                    host: &'static $crate::host::HostVTable,
+                    socket: $crate::plugin::RawUdpSocket,
+                ) -> $crate::plugin::FfiResult<()> {
+                    $crate::plugin::call_load($name, host, socket.into_socket(), $load)
+                }
+
+                vrc_osc_load
+            },
+            chat:        $crate::export_plugin!(@chat $($chat)?),
+            unload:      {
+                unsafe extern "C" fn vrc_osc_unload() -> bool {
+                    $crate::plugin::call_unload($crate::export_plugin!(@unload $($unload)?))
+                }
+
+                vrc_osc_unload
+            },
+            get_config:  $crate::export_plugin!(@get_config $($config)?),
+            set_config:  $crate::export_plugin!(@set_config $($config)?),
+            free_string: $crate::plugin::free_string,
+        };
+    };
+    (@loader_version) => {
+        ""
+    };
+    (@loader_version $loader_version:literal) => {
+        $loader_version
+    };
+    (@platforms) => {
+        $crate::plugin::Platforms::ALL
+    };
+    (@platforms $platforms:expr) => {{
+        use $crate::plugin::Platforms;
+        $platforms
+    }};
+    (@unload) => {
+        None
+    };
+    (@unload $unload:path) => {
+        Some($unload)
+    };
+    (@get_config) => {
+        None
+    };
+    (@get_config $config:ty) => {
+        Some({
+            unsafe extern "C" fn vrc_osc_get_config(
+            ) -> $crate::plugin::FfiResult<$crate::plugin::RawString> {
+                $crate::plugin::call_get_config::<$config>()
+            }
+
+            vrc_osc_get_config
+        })
+    };
+    (@set_config) => {
+        None
+    };
+    (@set_config $config:ty) => {
+        Some({
+            unsafe extern "C" fn vrc_osc_set_config(
+                config: $crate::plugin::RawStr,
+            ) -> $crate::plugin::FfiResult<()> {
+                $crate::plugin::call_set_config::<$config>(config.as_str())
+            }
+
+            vrc_osc_set_config
+        })
+    };
+    (@chat) => {
+        None
+    };
+    (@chat $chat:path) => {
+        Some({
+            unsafe extern "C" fn vrc_osc_chat(
+                chatbox: $crate::plugin::RawStr,
+                console: $crate::plugin::RawStr,
+            ) -> $crate::async_ffi::FfiFuture<
+                $crate::plugin::FfiResult<$crate::plugin::RawChatMessage>,
+            > {
+                let chatbox = chatbox.as_str().to_owned();
+                let console = console.as_str().to_owned();
+
+                $crate::plugin::call_chat(chatbox, console, $chat)
+            }
+
+            vrc_osc_chat
+        })
+    };
+}
+
+/// # Safety
+///
+/// Must only be called with strings allocated by this side of the boundary
+pub unsafe extern "C" fn free_string(string: RawString) {
+    drop(string.into_string());
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    panic
+        .downcast_ref::<&str>()
+        .map(ToString::to_string)
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "Unknown panic".into())
+}
+
+/// How long chats still running get when the plugin unloads
+const RUNTIME_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(1);
+
+static UNLOADING: AtomicBool = AtomicBool::new(false);
+static UNLOADED: Notify = Notify::const_new();
+
+/// Chats started and not finished yet, including those that never got to run
+static CHATS: AtomicUsize = AtomicUsize::new(0);
+
+/// Counts a chat from when it's spawned until its closure is dropped, whether it ran or not
+struct Chatting;
+
+impl Chatting {
+    fn new() -> Self {
+        CHATS.fetch_add(1, Ordering::SeqCst);
+        Self
+    }
+}
+
+impl Drop for Chatting {
+    fn drop(&mut self) {
+        CHATS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Returns `true` once the loader asked this plugin to unload
+///
+/// The loader wakes up plugins blocked on their socket with an empty packet,
+/// so check this after every receive and return from `load`
+#[must_use]
+pub fn unloading() -> bool {
+    UNLOADING.load(Ordering::SeqCst)
+}
+
+/// Waits until the loader asks this plugin to unload
+pub async fn unloaded() {
+    let notified = UNLOADED.notified();
+    if !unloading() {
+        notified.await;
+    }
+}
+
+#[doc(hidden)]
+#[must_use]
+pub fn call_unload(unload: Option<fn()>) -> bool {
+    UNLOADING.store(true, Ordering::SeqCst);
+    UNLOADED.notify_waiters();
+
+    if let Some(unload) = unload {
+        if let Err(panic) = std::panic::catch_unwind(unload) {
+            tracing::error!("Unload panicked: {}", panic_message(&*panic));
+        }
+    }
+
+    // Its threads would run code from the library after the loader closes it
+    let runtime = RUNTIME
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .take();
+    if let Some(runtime) = runtime {
+        runtime.shutdown_timeout(RUNTIME_SHUTDOWN_TIMEOUT);
+    }
+
+    CHATS.load(Ordering::SeqCst) == 0
+}
+
+#[doc(hidden)]
+pub fn call_load(
+    name: &'static str,
+    host: &'static HostVTable,
+    socket: UdpSocket,
+    load: fn(UdpSocket) -> Result<()>,
+) -> FfiResult<()> {
+    crate::host::set_host(host);
+    crate::logging::forward_to_host(name);
+
+    // Loaded again after being unloaded, such as when its config was changed
+    UNLOADING.store(false, Ordering::SeqCst);
+
+    match std::panic::catch_unwind(AssertUnwindSafe(|| load(socket))) {
+        Ok(Ok(())) => FfiResult::Ok(()),
+        Ok(Err(error)) => FfiResult::Err(format!("{error:#}").into()),
+        Err(panic) => FfiResult::Err(format!("Panicked: {}", panic_message(&*panic)).into()),
+    }
+}
+
+fn to_ffi<T>(result: Result<T>) -> FfiResult<T> {
+    match result {
+        Ok(value) => FfiResult::Ok(value),
+        Err(error) => FfiResult::Err(format!("{error:#}").into()),
+    }
+}
+
+#[doc(hidden)]
+pub fn call_get_config<T: DeriveTomlConfig + Default>() -> FfiResult<RawString> {
+    // Plugins start with the default config when theirs is missing or invalid
+    let config = T::load().unwrap_or_default();
+
+    to_ffi(
+        serde_json::to_string(&config)
+            .map(Into::into)
+            .map_err(Into::into),
+    )
+}
+
+#[doc(hidden)]
+#[must_use]
+pub fn call_set_config<T: DeriveTomlConfig>(config: &str) -> FfiResult<()> {
+    let result = serde_json::from_str::<T>(config)
+        .context("Invalid config")
+        .and_then(|config| Ok(config.save()?));
+
+    to_ffi(result)
+}
+
+/// The plugin's own runtime, the loader's runtime lives in a different copy of tokio
+///
+/// Shut down when the plugin unloads and started again by the next chat
+static RUNTIME: Mutex<Option<Runtime>> = Mutex::new(None);
+
+fn runtime() -> Handle {
+    RUNTIME
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get_or_insert_with(|| {
+            tokio::runtime::Builder::new_multi_thread()
+                .worker_threads(1)
+                .enable_all()
+                .build()
+                .expect("Failed to build the plugin runtime")
+        })
+        .handle()
+        .clone()
+}
+
+#[doc(hidden)]
+pub fn call_chat<F, Fut>(
+    chatbox: String,
+    console: String,
+    chat: F,
+) -> FfiFuture<FfiResult<RawChatMessage>>
+where
+    F: FnOnce(String, String) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<ChatMessage>>,
+{
+    // Chat futures aren't required to be Send, so they're driven on a blocking thread
+    let chatting = Chatting::new();
+    let task = runtime().spawn_blocking(move || {
+        let _chatting = chatting;
+        Handle::current().block_on(chat(chatbox, console))
+    });
+
+    async move {
+        match task.await {
+            Ok(Ok((chatbox, console))) => FfiResult::Ok(RawChatMessage {
+                chatbox: chatbox.into(),
+                console: console.into(),
+            }),
+            Ok(Err(error)) => FfiResult::Err(error.to_string().into()),
+            Err(error) => FfiResult::Err(error.to_string().into()),
+        }
+    }
+    .into_ffi()
+}
+
+/// Returned by [`Plugin::new`] when a library doesn't export the plugin symbols
+#[derive(Debug)]
+pub struct NotAPlugin(pub String);
+
+impl Display for NotAPlugin {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} is not a VRC-OSC plugin", self.0)
+    }
+}
+
+impl std::error::Error for NotAPlugin {}
+
+/// A loaded plugin library, the library is kept open for as long as this is alive
+pub struct Plugin {
+    pub filename: String,
+    pub metadata: Metadata,
+    /// The parsed [`Metadata::subscriptions`]
+    pub subscriptions: Vec<Pattern>,
+    vtable: PluginVTable,
+    _library: Library,
+}
+
+impl Plugin {
+    /// # Errors
+    ///
+    /// Will return `Err` if the library couldn't be opened, isn't a plugin ([`NotAPlugin`]),
+    /// was built for a different plugin API version, or doesn't support this platform or loader
+    pub fn new(filename: &str) -> Result<Self> {
+        let path = crate::get_plugin_path(filename.to_owned())?;
+
+        Self::with_path(filename, path.as_ref())
+    }
+
+    /// Opens the plugin from another path, such as a shadow copy
+    ///
+    /// # Errors
+    ///
+    /// Same as [`Plugin::new`]
+    pub fn with_path(filename: &str, path: &Path) -> Result<Self> {
+        let library = unsafe { Library::new(path) }?;
+
+        let api_version = unsafe {
+            **library
+                .get::<*const u32>(b"VRC_OSC_API_VERSION")
+                .map_err(|_| NotAPlugin(filename.to_owned()))?
+        };
+
+        if api_version != API_VERSION {
+            bail!("{filename} was built for plugin API v{api_version} but the loader requires v{API_VERSION}, please update it");
+        }
+
+        let vtable = unsafe {
+            **library
+                .get::<*const PluginVTable>(b"VRC_OSC_PLUGIN")
+                .with_context(|| format!("{filename} is missing its plugin vtable"))?
+        };
+
+        let metadata = unsafe { Metadata::from_raw(vtable.metadata) };
+        if !metadata.platforms.contains(Platforms::current()) {
+            bail!("{} doesn't support {}", metadata.name, std::env::consts::OS);
+        }
+
+        if requires_newer_loader(&metadata.loader_version) {
+            bail!(
+                "{} requires VRC-OSC v{} or newer but this is v{CARGO_PKG_VERSION}, please update the loader",
+                metadata.name,
+                metadata.loader_version
+            );
+        }
+
+        let subscriptions = metadata
+            .subscriptions
+            .iter()
+            .map(|subscription| Pattern::new(subscription))
+            .collect::<Result<_>>()
+            .with_context(|| format!("{} has an invalid subscription", metadata.name))?;
+
+        Ok(Self {
+            filename: filename.to_owned(),
+            metadata,
+            subscriptions,
+            vtable,
+            _library: library,
+        })
+    }
+
+    fn take_string(&self, string: RawString) -> String {
+        let owned = string.as_str().to_owned();
+        unsafe { (self.vtable.free_string)(string) };
+
+        owned
+    }
+
+    /// # Errors
+    ///
+    /// Will return `Err` if the plugin returned an error or panicked
+    pub fn load(&self, socket: UdpSocket) -> Result<()> {
+        match unsafe { (self.vtable.load)(&HOST_VTABLE, socket.into()) } {
+            FfiResult::Ok(()) => Ok(()),
+            FfiResult::Err(error) => bail!(self.take_string(error)),
+        }
+    }
+
+    /// Asks the plugin to return from `load`, see [`unloading`]
+    ///
+    /// Returns `false` if its chats didn't finish in time, their threads still run code from the library
+    #[must_use]
+    pub fn unload(&self) -> bool {
+        unsafe { (self.vtable.unload)() }
+    }
+
+    /// The plugin's config file as JSON, `None` if the plugin doesn't export its config
+    pub fn config(&self) -> Option<Result<serde_json::Value>> {
+        let get_config = self.vtable.get_config?;
+
+        Some(match unsafe { get_config() } {
+            FfiResult::Ok(config) => {
+                serde_json::from_str(&self.take_string(config)).map_err(Into::into)
+            }
+            FfiResult::Err(error) => Err(anyhow!(self.take_string(error))),
+        })
+    }
+
+    /// Checks the config against the plugin's config struct and saves it, which the plugin reads when loaded
+    ///
+    /// Returns `None` if the plugin doesn't export its config
+    #[must_use]
+    pub fn set_config(&self, config: &serde_json::Value) -> Option<Result<()>> {
+        let set_config = self.vtable.set_config?;
+        let config = config.to_string();
+
+        Some(match unsafe { set_config(RawStr::new(&config)) } {
+            FfiResult::Ok(()) => Ok(()),
+            FfiResult::Err(error) => Err(anyhow!(self.take_string(error))),
+        })
+    }
+
+    /// Returns `None` if the plugin isn't a chat provider
+    pub async fn chat(&self, (chatbox, console): &ChatMessage) -> Option<Result<ChatMessage>> {
+        let chat_fn = self.vtable.chat?;
+        let future = unsafe { chat_fn(RawStr::new(chatbox), RawStr::new(console)) };
+
+        Some(match future.await {
+            FfiResult::Ok(message) => Ok((
+                self.take_string(message.chatbox),
+                self.take_string(message.console),
+            )),
+            FfiResult::Err(error) => Err(anyhow!(self.take_string(error))),
+        })
+    }
+}
+
+/// Compares `major.minor.patch` with the loader's version, parts that aren't numbers count as 0
+fn requires_newer_loader(loader_version: &str) -> bool {
+    let parse = |version: &str| {
+        version
+            .split('.')
+            .map(|part| part.parse::<u64>().unwrap_or_default())
+            .collect::<Vec<_>>()
+    };
+
+    parse(loader_version) > parse(CARGO_PKG_VERSION)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plugins_can_require_a_newer_loader() {
+        let [major, minor, patch] = [0, 1, 2].map(|index| {
+            CARGO_PKG_VERSION
+                .split('.')
+                .nth(index)
+                .unwrap()
+                .parse::<u64>()
+                .unwrap()
+        });
+
+        assert!(!requires_newer_loader(""));
+        assert!(!requires_newer_loader(CARGO_PKG_VERSION));
+        assert!(!requires_newer_loader(&format!("{major}.{minor}")));
+        assert!(requires_newer_loader(&format!(
+            "{major}.{minor}.{}",
+            patch + 1
+        )));
+        assert!(requires_newer_loader(&format!("{major}.{}.0", minor + 1)));
+        assert!(requires_newer_loader(&format!("{}.0.0", major + 1)));
+    }
+}
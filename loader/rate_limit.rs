@@ -0,0 +1,471 @@
+use std::{
+    collections::VecDeque,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use rosc::OscPacket;
+use serde::{Deserialize, Serialize};
+
+use crate::pattern::Pattern;
+
+/// How often queued messages are checked for tokens
+pub const TICK: Duration = Duration::from_millis(50);
+
+/// Queued packets per limit, anything past this is dropped
+const MAX_QUEUED: usize = 256;
+
+/// A token bucket shared by every plugin for the outgoing addresses matching a pattern
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RateLimit {
+    pub pattern:  String,
+    /// Milliseconds between tokens
+    pub interval: u64,
+    /// Messages that can be sent at once after being idle
+    pub burst:    u32,
+    pub overflow: Overflow,
+}
+
+/// What to do with messages sent while a limit is out of tokens
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Overflow {
+    /// Sends them in order as tokens become available
+    Queue,
+    /// Only sends the latest message for each address
+    #[default]
+    Coalesce,
+    Drop,
+}
+
+impl RateLimit {
+    /// VRChat times out the chatbox when it's sent to faster than every 1.5 seconds
+    #[must_use]
+    pub fn defaults() -> Vec<Self> {
+        vec![Self {
+            pattern:  "/chatbox/input".into(),
+            interval: 1500,
+            burst:    1,
+            overflow: Overflow::Coalesce,
+        }]
+    }
+}
+
+#[derive(Default)]
+struct Throttled {
+    queued:    u64,
+    coalesced: u64,
+    dropped:   u64,
+}
+
+struct Bucket {
+    limit:     RateLimit,
+    pattern:   Pattern,
+    tokens:    f64,
+    updated:   Instant,
+    /// Packets and who sent them
+    queue:     VecDeque<(String, OscPacket)>,
+    /// Counted from the first throttled packet until the bucket is full again
+    throttled: Option<Throttled>,
+}
+
+impl Bucket {
+    #[allow(clippy::cast_precision_loss)]
+    fn refill(&mut self, now: Instant) {
+        let interval = self.limit.interval.max(1) as f64;
+        let elapsed = now.duration_since(self.updated).as_secs_f64() * 1000.0;
+        let burst = f64::from(self.limit.burst.max(1));
+
+        self.tokens = elapsed.mul_add(1.0 / interval, self.tokens).min(burst);
+        self.updated = now;
+
+        if self.queue.is_empty() && self.tokens >= burst {
+            if let Some(throttled) = self.throttled.take() {
+                tracing::info!(
+                    "Stopped rate limiting {} after queueing {}, coalescing {} and dropping {} packets",
+                    self.limit.pattern, throttled.queued, throttled.coalesced, throttled.dropped
+                );
+            }
+        }
+    }
+
+    fn take(&mut self) -> bool {
+        if self.tokens < 1.0 {
+            return false;
+        }
+
+        self.tokens -= 1.0;
+        true
+    }
+
+    fn has_token(&self) -> bool {
+        self.tokens >= 1.0
+    }
+
+    /// Packets can't skip ahead of the queue
+    fn can_send(&self) -> bool {
+        self.queue.is_empty() && self.has_token()
+    }
+
+    fn overflow(&mut self, packet: OscPacket, source: &str) {
+        let throttled = self.throttled.get_or_insert_with(|| {
+            tracing::warn!("{source} hit the {} rate limit", self.limit.pattern);
+
+            Throttled::default()
+        });
+
+        // Bundles only replace bundles with the same addresses
+        let addrs = addrs(&packet);
+        let queued = self
+            .queue
+            .iter()
+            .position(|(_, queued)| self::addrs(queued) == addrs);
+
+        match (self.limit.overflow, queued) {
+            (Overflow::Coalesce, Some(index)) => {
+                self.queue[index] = (source.to_owned(), packet);
+                throttled.coalesced += 1;
+            }
+            (Overflow::Queue | Overflow::Coalesce, _) if self.queue.len() < MAX_QUEUED => {
+                self.queue.push_back((source.to_owned(), packet));
+                throttled.queued += 1;
+            }
+            _ => throttled.dropped += 1,
+        }
+    }
+}
+
+/// Limits how fast plugins can send to VRChat, see [`RateLimit`]
+pub struct RateLimiter {
+    buckets: Mutex<Vec<Bucket>>,
+}
+
+impl RateLimiter {
+    /// # Errors
+    ///
+    /// Will return `Err` if a pattern is invalid
+    pub fn new(limits: &[RateLimit]) -> Result<Self> {
+        let now = Instant::now();
+        let buckets = limits
+            .iter()
+            .map(|limit| {
+                let pattern = Pattern::new(&limit.pattern)
+                    .with_context(|| format!("Invalid rate limit pattern {}", limit.pattern))?;
+
+                Ok(Bucket {
+                    limit: limit.clone(),
+                    pattern,
+                    tokens: f64::from(limit.burst.max(1)),
+                    updated: now,
+                    queue: VecDeque::new(),
+                    throttled: None,
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            buckets: Mutex::new(buckets),
+        })
+    }
+
+    /// # Panics
+    ///
+    /// Will panic if the buckets lock was poisoned
+    #[must_use]
+    pub fn is_enabled(&self) -> bool {
+        !self.lock().is_empty()
+    }
+
+    /// Takes a token from every limit the packet's messages match, returns `false` if it can't be sent now
+    ///
+    /// Bundles are applied by VRChat at once, so they take one token per limit and are sent, queued,
+    /// coalesced or dropped whole
+    ///
+    /// # Panics
+    ///
+    /// Will panic if the buckets lock was poisoned
+    pub fn limit(&self, packet: &OscPacket, source: &str) -> bool {
+        let mut buckets = self.lock();
+        if buckets.is_empty() {
+            return true;
+        }
+
+        let now = Instant::now();
+        buckets.iter_mut().for_each(|bucket| bucket.refill(now));
+
+        let remaining = limit_packet(&mut buckets, packet, source);
+        drop(buckets);
+
+        remaining
+    }
+
+    /// Queued packets that have a token now and who sent them, in the order they were sent
+    ///
+    /// A packet is queued by the first limit it was short of, but is only released once every limit
+    /// it matches has a token, taking one from each
+    ///
+    /// # Panics
+    ///
+    /// Will panic if the buckets lock was poisoned
+    pub fn ready(&self) -> Vec<(String, OscPacket)> {
+        let now = Instant::now();
+        let mut buckets = self.lock();
+        buckets.iter_mut().for_each(|bucket| bucket.refill(now));
+
+        let mut ready = Vec::new();
+        for index in 0..buckets.len() {
+            while let Some((_, packet)) = buckets[index].queue.front() {
+                let matching = matching(&buckets, packet);
+                if !matching.iter().all(|&index| buckets[index].has_token()) {
+                    break;
+                }
+
+                for index in matching {
+                    buckets[index].take();
+                }
+
+                ready.extend(buckets[index].queue.pop_front());
+            }
+        }
+        drop(buckets);
+
+        ready
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<Bucket>> {
+        self.buckets.lock().expect("Failed to lock the rate limits")
+    }
+}
+
+/// The limits the packet's messages match, each once, a message only matches the first limit
+fn matching(buckets: &[Bucket], packet: &OscPacket) -> Vec<usize> {
+    let mut matching = Vec::new();
+    for addr in addrs(packet) {
+        let bucket = buckets
+            .iter()
+            .position(|bucket| bucket.pattern.matches(addr));
+
+        if let Some(index) = bucket.filter(|index| !matching.contains(index)) {
+            matching.push(index);
+        }
+    }
+
+    matching
+}
+
+fn limit_packet(buckets: &mut [Bucket], packet: &OscPacket, source: &str) -> bool {
+    let limited = matching(buckets, packet);
+    if let Some(&index) = limited.iter().find(|&&index| !buckets[index].can_send()) {
+        buckets[index].overflow(packet.clone(), source);
+        return false;
+    }
+
+    for index in limited {
+        buckets[index].take();
+    }
+
+    true
+}
+
+/// The address of every message in the packet, including those inside bundles
+fn addrs(packet: &OscPacket) -> Vec<&str> {
+    match packet {
+        OscPacket::Message(message) => vec![message.addr.as_str()],
+        OscPacket::Bundle(bundle) => bundle.content.iter().flat_map(addrs).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rosc::{OscBundle, OscMessage, OscType};
+
+    use super::*;
+
+    fn limiter(pattern: &str, burst: u32, overflow: Overflow) -> RateLimiter {
+        RateLimiter::new(&[RateLimit {
+            pattern: pattern.into(),
+            interval: 1000,
+            burst,
+            overflow,
+        }])
+        .unwrap()
+    }
+
+    fn message(addr: &str, value: i32) -> OscPacket {
+        OscPacket::Message(OscMessage {
+            addr: addr.into(),
+            args: vec![OscType::Int(value)],
+        })
+    }
+
+    fn bundle(packets: Vec<OscPacket>) -> OscPacket {
+        OscPacket::Bundle(OscBundle {
+            timetag: (0, 1).into(),
+            content: packets,
+        })
+    }
+
+    /// Pretends the bucket was last refilled that long ago
+    fn wait(limiter: &RateLimiter, duration: Duration) {
+        for bucket in limiter.lock().iter_mut() {
+            bucket.updated -= duration;
+        }
+    }
+
+    #[test]
+    fn sends_a_burst_then_throttles() {
+        let limiter = limiter("/input/*", 3, Overflow::Drop);
+        for value in 0..3 {
+            assert!(limiter.limit(&message("/input/Jump", value), "Test"));
+        }
+
+        assert!(!limiter.limit(&message("/input/Jump", 3), "Test"));
+        assert!(limiter.ready().is_empty());
+    }
+
+    #[test]
+    fn refills_a_token_every_interval_up_to_the_burst() {
+        let limiter = limiter("/input/*", 2, Overflow::Drop);
+        assert!(limiter.limit(&message("/input/Jump", 0), "Test"));
+        assert!(limiter.limit(&message("/input/Jump", 1), "Test"));
+        assert!(!limiter.limit(&message("/input/Jump", 2), "Test"));
+
+        wait(&limiter, Duration::from_secs(1));
+        assert!(limiter.limit(&message("/input/Jump", 3), "Test"));
+        assert!(!limiter.limit(&message("/input/Jump", 4), "Test"));
+
+        wait(&limiter, Duration::from_secs(10));
+        assert!(limiter.limit(&message("/input/Jump", 5), "Test"));
+        assert!(limiter.limit(&message("/input/Jump", 6), "Test"));
+        assert!(!limiter.limit(&message("/input/Jump", 7), "Test"));
+    }
+
+    #[test]
+    fn sources_share_the_bucket_of_a_pattern() {
+        let limiter = RateLimiter::new(&[
+            RateLimit {
+                pattern:  "/chatbox/input".into(),
+                interval: 1000,
+                burst:    1,
+                overflow: Overflow::Queue,
+            },
+            RateLimit {
+                pattern:  "/input/*".into(),
+                interval: 1000,
+                burst:    1,
+                overflow: Overflow::Queue,
+            },
+        ])
+        .unwrap();
+
+        assert!(limiter.limit(&message("/chatbox/input", 0), "Spotify"));
+        assert!(!limiter.limit(&message("/chatbox/input", 1), "Chatbox"));
+        assert!(limiter.limit(&message("/input/Jump", 0), "Chatbox"));
+        assert!(limiter.limit(&message("/avatar/parameters/Muted", 0), "Chatbox"));
+
+        wait(&limiter, Duration::from_secs(1));
+        assert_eq!(
+            limiter.ready(),
+            [("Chatbox".into(), message("/chatbox/input", 1))]
+        );
+    }
+
+    #[test]
+    fn queues_in_order_and_coalesces_by_address() {
+        let queue = limiter("/input/*", 1, Overflow::Queue);
+        let coalesce = limiter("/input/*", 1, Overflow::Coalesce);
+        for limiter in [&queue, &coalesce] {
+            for value in 0..3 {
+                limiter.limit(&message("/input/Jump", value), "Test");
+            }
+
+            limiter.limit(&message("/input/Run", 0), "Test");
+            wait(limiter, Duration::from_secs(10));
+        }
+
+        let sent = |limiter: &RateLimiter| {
+            let mut sent = Vec::new();
+            loop {
+                let ready = limiter.ready();
+                if ready.is_empty() {
+                    return sent;
+                }
+
+                sent.extend(ready.into_iter().map(|(_, packet)| packet));
+                wait(limiter, Duration::from_secs(10));
+            }
+        };
+
+        assert_eq!(
+            sent(&queue),
+            [
+                message("/input/Jump", 1),
+                message("/input/Jump", 2),
+                message("/input/Run", 0)
+            ]
+        );
+        assert_eq!(
+            sent(&coalesce),
+            [message("/input/Jump", 2), message("/input/Run", 0)]
+        );
+    }
+
+    #[test]
+    fn charges_and_throttles_bundles_whole() {
+        let limiter = limiter("/avatar/parameters/*", 2, Overflow::Queue);
+        let clock = bundle(vec![
+            message("/avatar/parameters/Hours", 1),
+            message("/avatar/parameters/Minutes", 2),
+            message("/avatar/parameters/Seconds", 3),
+            message("/chatbox/typing", 1),
+        ]);
+
+        // One token for the whole bundle, even with more messages than the burst
+        assert!(limiter.limit(&clock, "Clock"));
+        assert!(limiter.limit(&clock, "Clock"));
+        assert!(!limiter.limit(&clock, "Clock"));
+
+        wait(&limiter, Duration::from_secs(1));
+        assert_eq!(limiter.ready(), [("Clock".into(), clock)]);
+    }
+
+    #[test]
+    fn queued_bundles_wait_for_every_limit_they_match() {
+        let limiter = RateLimiter::new(&[
+            RateLimit {
+                pattern:  "/input/*".into(),
+                interval: 1000,
+                burst:    1,
+                overflow: Overflow::Queue,
+            },
+            RateLimit {
+                pattern:  "/chatbox/*".into(),
+                interval: 10000,
+                burst:    1,
+                overflow: Overflow::Queue,
+            },
+        ])
+        .unwrap();
+
+        let both = bundle(vec![
+            message("/input/Jump", 1),
+            message("/chatbox/typing", 1),
+        ]);
+
+        assert!(limiter.limit(&message("/input/Jump", 0), "Test"));
+        assert!(limiter.limit(&message("/chatbox/typing", 0), "Test"));
+        assert!(!limiter.limit(&both, "Test"));
+
+        // Queued by /input/* which has a token again, but /chatbox/* doesn't yet
+        wait(&limiter, Duration::from_secs(1));
+        assert!(limiter.ready().is_empty());
+
+        wait(&limiter, Duration::from_secs(10));
+        assert_eq!(limiter.ready(), [("Test".into(), both)]);
+
+        // Both limits were charged
+        assert!(!limiter.limit(&message("/input/Run", 0), "Test"));
+        assert!(!limiter.limit(&message("/chatbox/typing", 0), "Test"));
+    }
+}
@@ -0,0 +1,211 @@
+use std::{
+    collections::HashMap,
+    net::UdpSocket,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+
+use crate::{plugin::Plugin, supervisor::RunningPlugin, Config, RunningPlugins};
+
+const UNLOAD_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn shadow_dir() -> PathBuf {
+    std::env::temp_dir().join("vrc-osc")
+}
+
+/// Copies the plugin to a path of its own, so the original can be rebuilt while loaded
+///
+/// Every copy is a new path, so opening it loads a new instance with fresh statics
+/// even while the previous instance is still open
+///
+/// # Errors
+///
+/// Will return `Err` if the plugin couldn't be read or copied
+pub fn shadow_copy(filename: &str) -> Result<PathBuf> {
+    static COPIES: AtomicU64 = AtomicU64::new(0);
+
+    let source = PathBuf::from(crate::get_plugin_path(filename.to_owned())?);
+    let stem = source.file_stem().context("None")?.to_string_lossy();
+    let extension = source.extension().context("None")?.to_string_lossy();
+    let copy = COPIES.fetch_add(1, Ordering::Relaxed);
+    let path = shadow_dir().join(format!("{stem}-{}-{copy}.{extension}", std::process::id()));
+
+    std::fs::create_dir_all(shadow_dir())?;
+    std::fs::copy(&source, &path)?;
+
+    Ok(path)
+}
+
+/// Opens a new instance of the plugin from a shadow copy, see [`shadow_copy`]
+///
+/// # Errors
+///
+/// Will return `Err` if the plugin couldn't be copied or opened
+pub fn open_fresh(filename: &str) -> Result<Plugin> {
+    let path = shadow_copy(filename)?;
+    let plugin = Plugin::with_path(filename, &path);
+
+    // Stays mapped once opened, Windows keeps it until the next start cleans it up
+    let _ = std::fs::remove_file(&path);
+
+    plugin
+}
+
+/// Removes shadow copies left behind by previous runs
+pub fn clean_shadow_copies() {
+    let Ok(entries) = std::fs::read_dir(shadow_dir()) else {
+        return; // Nothing to clean
+    };
+
+    for entry in entries.filter_map(Result::ok) {
+        let _ = std::fs::remove_file(entry.path());
+    }
+}
+
+/// Polls the enabled plugins and reloads the ones whose library changed
+pub fn watch(plugins: RunningPlugins, loader_socket: Arc<UdpSocket>, config: Config) {
+    tokio::spawn(async move {
+        let mut loaded = HashMap::new();
+        let mut pending = HashMap::new();
+        let mut interval = tokio::time::interval(Duration::from_secs(1));
+
+        loop {
+            interval.tick().await;
+
+            for filename in &config.enabled {
+                let Ok(path) = crate::get_plugin_path(filename.clone()) else {
+                    continue;
+                };
+
+                let Ok(modified) = std::fs::metadata(path).and_then(|metadata| metadata.modified())
+                else {
+                    continue; // Missing while it's being rebuilt
+                };
+
+                if *loaded.entry(filename.clone()).or_insert(modified) == modified {
+                    continue; // Unchanged
+                }
+
+                // Wait until the library stops changing before loading it
+                if pending.insert(filename.clone(), modified) != Some(modified) {
+                    continue;
+                }
+
+                pending.remove(filename);
+                loaded.insert(filename.clone(), modified);
+
+                tracing::info!("Reloading {filename}");
+                if let Err(error) = reload(filename, &plugins, &loader_socket, &config).await {
+                    tracing::error!("Failed to reload {filename}: {error:#}");
+                }
+            }
+        }
+    });
+}
+
+/// Opens a new instance of the plugin and swaps it in for the running instance, or starts it if it isn't running
+///
+/// The new instance starts with fresh statics, so it reads its config again
+///
+/// # Errors
+///
+/// Will return `Err` if the plugin couldn't be opened or the running instance didn't stop, keeping it,
+/// or the new instance couldn't be started
+pub async fn reload(
+    filename: &str,
+    plugins: &RunningPlugins,
+    loader_socket: &UdpSocket,
+    config: &Config,
+) -> Result<()> {
+    // Open the new build first so a broken build doesn't unload the working one
+    let plugin = open_fresh(filename)?;
+
+    swap(plugin, plugins, loader_socket, config).await
+}
+
+/// Stops the running instance of the plugin and starts the opened one in its place
+///
+/// # Errors
+///
+/// Will return `Err` if the running instance didn't stop, keeping it, or the new instance couldn't be started
+///
+/// # Panics
+///
+/// Will panic if the plugins lock was poisoned
+pub async fn swap(
+    plugin: Plugin,
+    plugins: &RunningPlugins,
+    loader_socket: &UdpSocket,
+    config: &Config,
+) -> Result<()> {
+    let filename = plugin.filename.clone();
+    let old = running(&filename, plugins);
+
+    // Keep routing to the old instance until it has stopped
+    if let Some(old) = &old {
+        stop(old, loader_socket).await?;
+    }
+
+    let running = Arc::new(crate::start_plugin(plugin, config)?);
+    let mut plugins = plugins.write().expect("Failed to write plugins");
+    plugins.retain(|running| running.plugin.filename != filename);
+    plugins.push(running);
+    drop(plugins);
+
+    // Closes the old library once the router and chats let go of it
+    drop(old);
+
+    Ok(())
+}
+
+/// Stops the plugin and routing to it, closing its library once nothing uses it
+///
+/// # Errors
+///
+/// Will return `Err` if the plugin didn't stop, it's left loaded and can be unloaded again later
+///
+/// # Panics
+///
+/// Will panic if the plugins lock was poisoned
+pub async fn unload(
+    filename: &str,
+    plugins: &RunningPlugins,
+    loader_socket: &UdpSocket,
+) -> Result<()> {
+    let old = running(filename, plugins);
+
+    let Some(old) = old else {
+        return Ok(()); // Not running
+    };
+
+    stop(&old, loader_socket).await?;
+    plugins
+        .write()
+        .expect("Failed to write plugins")
+        .retain(|running| running.plugin.filename != filename);
+
+    Ok(())
+}
+
+fn running(filename: &str, plugins: &RunningPlugins) -> Option<Arc<RunningPlugin>> {
+    plugins
+        .read()
+        .expect("Failed to read plugins")
+        .iter()
+        .find(|running| running.plugin.filename == filename)
+        .cloned()
+}
+
+/// Plugins that didn't stop are kept running instead of being dropped, which would close their library
+/// while their threads still run its code
+async fn stop(old: &RunningPlugin, loader_socket: &UdpSocket) -> Result<()> {
+    old.stop(loader_socket, UNLOAD_TIMEOUT)
+        .await
+        .context("It's left running, try again once it stops")
+}
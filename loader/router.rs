@@ -0,0 +1,658 @@
+use std::{
+    collections::BTreeMap,
+    net::{SocketAddr, TcpListener, UdpSocket},
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, SyncSender, TrySendError},
+        Arc,
+        Mutex,
+    },
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Context, Result};
+use rosc::{OscMessage, OscPacket, OscType};
+
+use crate::{
+    avatar::{self, AVATAR_CHANGE},
+    forward::Forward,
+    metrics,
+    parameters,
+    pattern::Pattern,
+    rate_limit::{self, RateLimiter},
+    schedule::{self, Schedule},
+    session::{self, Direction, Recorder},
+    supervisor::RunningPlugin,
+    tcp::{Framing, OscStream, HELLO},
+    validation::Validator,
+    Config,
+    RunningPlugins,
+};
+
+/// Relays outgoing packets from plugins and forward targets, and incoming packets to the plugins
+/// and forward targets subscribed to them
+pub struct Router {
+    socket:    Arc<UdpSocket>,
+    send_addr: String,
+    plugins:   RunningPlugins,
+    validator: Validator,
+    limiter:   Arc<RateLimiter>,
+    forwards:  Vec<Arc<Forward>>,
+    tcp:       Option<(TcpListener, Framing)>,
+    tcp_peers: Mutex<Vec<TcpPeer>>,
+    scheduled: Schedule<Scheduled>,
+    recorder:  Option<Recorder>,
+    replay:    Option<(PathBuf, f64)>,
+}
+
+/// Packets in bundles with a future timetag
+enum Scheduled {
+    /// VRChat -> Plugins
+    Incoming(Vec<OscMessage>),
+    /// Plugins -> VRChat, and who sent it
+    Outgoing(OscPacket, String),
+}
+
+/// A connected TCP peer, written to from its own thread so a slow peer can't hold up routing
+struct TcpPeer {
+    addr:      SocketAddr,
+    /// The plugin that connected, `None` for other apps which receive everything VRChat sends
+    plugin:    Option<SocketAddr>,
+    stream:    OscStream,
+    queue:     SyncSender<Vec<u8>>,
+    connected: bool,
+}
+
+/// Packets queued for a TCP peer before it's disconnected for not keeping up
+const TCP_QUEUE: usize = 1024;
+
+/// How long writing to a TCP peer may block before it's disconnected
+const TCP_WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// UDP allows bigger datagrams than rosc's `MTU`, VRChat's bundles can be too
+const MAX_DATAGRAM: usize = 65_536;
+
+pub const CHATBOX_INPUT: &str = "/chatbox/input";
+
+/// The last message sent to the chatbox
+static CHATBOX: Mutex<Option<String>> = Mutex::new(None);
+
+/// The last message any plugin or app sent to VRChat's chatbox
+///
+/// # Panics
+///
+/// Will panic if the chatbox lock was poisoned
+#[must_use]
+pub fn chatbox() -> Option<String> {
+    CHATBOX.lock().expect("Failed to lock the chatbox").clone()
+}
+
+impl Router {
+    /// # Errors
+    ///
+    /// Will return `Err` if a rate limit or forward target is invalid, or `tcp_addr` or `record`
+    /// couldn't be opened
+    pub fn new(socket: Arc<UdpSocket>, plugins: RunningPlugins, config: &Config) -> Result<Self> {
+        let forwards = config
+            .forward
+            .iter()
+            .map(|target| Forward::new(target).map(Arc::new))
+            .collect::<Result<_>>()?;
+
+        let tcp = config
+            .tcp_addr
+            .as_ref()
+            .map(|tcp_addr| {
+                TcpListener::bind(tcp_addr)
+                    .with_context(|| format!("Failed to bind {tcp_addr}"))
+                    .map(|listener| (listener, config.tcp_framing))
+            })
+            .transpose()?;
+
+        let recorder = config
+            .record
+            .as_ref()
+            .map(|path| Recorder::create(Path::new(path)))
+            .transpose()?;
+
+        let replay = config
+            .replay
+            .as_ref()
+            .map(|path| (PathBuf::from(path), config.replay_speed));
+
+        Ok(Self {
+            socket,
+            send_addr: config.send_addr.clone(),
+            plugins,
+            validator: Validator::new(config.validation),
+            limiter: Arc::new(RateLimiter::new(&config.rate_limits)?),
+            forwards,
+            tcp,
+            tcp_peers: Mutex::default(),
+            scheduled: Schedule::default(),
+            recorder,
+            replay,
+        })
+    }
+
+    /// The filters of every forward target, VRChat needs to send these to the loader
+    #[must_use]
+    pub fn forwarded(&self) -> Vec<Pattern> {
+        self.forwards
+            .iter()
+            .flat_map(|forward| forward.filters.clone())
+            .collect()
+    }
+
+    /// Routes packets until the socket fails
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if couldn't send a packet
+    pub fn run(self: Arc<Self>) -> Result<()> {
+        let router = self;
+        if router.limiter.is_enabled() {
+            let router = router.clone();
+            std::thread::spawn(move || router.send_queued());
+        }
+
+        if router.replay.is_some() {
+            let router = router.clone();
+            std::thread::spawn(move || router.replay());
+        }
+
+        for forward in &router.forwards {
+            if forward.socket.is_some() {
+                let router = router.clone();
+                let forward = forward.clone();
+                std::thread::spawn(move || router.relay(&forward));
+            }
+        }
+
+        if router.tcp.is_some() {
+            let router = router.clone();
+            std::thread::spawn(move || router.accept());
+        }
+
+        let scheduler = router.clone();
+        std::thread::spawn(move || scheduler.send_scheduled());
+
+        router.receive()
+    }
+
+    fn receive(&self) -> Result<()> {
+        let mut buf = vec![0u8; MAX_DATAGRAM];
+        loop {
+            let Ok((size, recv_addr)) = self.socket.recv_from(&mut buf) else {
+                continue;
+            };
+
+            self.route(&buf[..size], recv_addr)?;
+        }
+    }
+
+    /// # Errors
+    ///
+    /// Will return `Err` if couldn't send the packet
+    ///
+    /// # Panics
+    ///
+    /// Will panic if the plugins lock was poisoned
+    pub fn route(&self, buf: &[u8], recv_addr: SocketAddr) -> Result<()> {
+        let plugins = self.plugins.read().expect("Failed to read plugins").clone();
+
+        // Plugins -> VRChat
+        if let Some(plugin) = plugins.iter().find(|plugin| plugin.addr == recv_addr) {
+            return self.outgoing(buf, plugin);
+        }
+
+        self.incoming(buf, &plugins)
+    }
+
+    /// Plugins -> VRChat, through validation and the rate limits
+    fn outgoing(&self, buf: &[u8], plugin: &RunningPlugin) -> Result<()> {
+        metrics::plugin_traffic(&plugin.plugin.metadata.name, Direction::Outgoing, buf.len());
+        let Ok((_buf, mut packet)) = rosc::decoder::decode_udp(buf) else {
+            return Ok(()); // VRChat can't read it either
+        };
+
+        if !self.validator.validate(&mut packet, plugin) {
+            return Ok(()); // Dropped
+        }
+
+        if !self.limiter.limit(&packet, &plugin.plugin.metadata.name) {
+            return Ok(()); // Throttled
+        }
+
+        let modified = self.validator.is_enabled();
+        let source = &plugin.plugin.metadata.name;
+        self.send_vrchat(packet, (!modified).then_some(buf), source)
+    }
+
+    /// VRChat -> Plugins
+    fn incoming(&self, buf: &[u8], plugins: &[Arc<RunningPlugin>]) -> Result<()> {
+        let Ok((_buf, packet)) = rosc::decoder::decode_udp(buf) else {
+            return Ok(()); // Not an OSC packet
+        };
+
+        if let Some(recorder) = &self.recorder {
+            recorder.record(Direction::Incoming, None, buf);
+        }
+
+        for message in messages(&packet) {
+            metrics::address_traffic(message, Direction::Incoming);
+        }
+
+        // Forward targets get the packet as is, timetags included
+        let mut now = Vec::new();
+        let mut later = BTreeMap::<SystemTime, Vec<OscMessage>>::new();
+        for (at, message) in timed_messages(&packet, None) {
+            match at {
+                Some(at) => later.entry(at).or_default().push(message.clone()),
+                None => now.push(message),
+            }
+        }
+
+        for (at, messages) in later {
+            self.schedule(at, Scheduled::Incoming(messages), "VRChat");
+        }
+
+        self.deliver(&now, plugins)?;
+
+        let addrs = messages(&packet)
+            .iter()
+            .map(|message| message.addr.as_str())
+            .collect::<Vec<_>>();
+
+        for forward in &self.forwards {
+            if !forward.wants(&addrs) {
+                continue;
+            }
+
+            if let Err(error) = self.socket.send_to(buf, forward.addr) {
+                tracing::error!("Failed to forward a packet to {}: {error}", forward.name);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sends VRChat's messages to the plugins subscribed to them and to TCP peers, one packet per message
+    fn deliver(&self, messages: &[&OscMessage], plugins: &[Arc<RunningPlugin>]) -> Result<()> {
+        if let Some(change) = messages
+            .iter()
+            .find(|message| message.addr == AVATAR_CHANGE)
+        {
+            let id = change.args.first().cloned().and_then(OscType::string);
+            avatar::notify_changed(id);
+            parameters::reset();
+            self.validator.avatar_changed();
+        }
+
+        let mut peers = self.tcp_peers.lock().expect("Failed to lock the TCP peers");
+        let listeners = peers.iter().any(|peer| peer.plugin.is_none());
+        for &message in messages {
+            parameters::record(message);
+
+            let subscribers = plugins
+                .iter()
+                .filter(|plugin| {
+                    plugin
+                        .plugin
+                        .subscriptions
+                        .iter()
+                        .any(|subscription| subscription.matches(&message.addr))
+                })
+                .collect::<Vec<_>>();
+
+            if subscribers.is_empty() && !listeners {
+                continue;
+            }
+
+            let buf = rosc::encoder::encode(&OscPacket::Message(message.clone()))?;
+            for plugin in subscribers {
+                // Plugins connected over TCP get their packets there instead
+                match peers
+                    .iter_mut()
+                    .find(|peer| peer.plugin == Some(plugin.addr))
+                {
+                    Some(peer) => peer.send(&buf),
+                    None => {
+                        self.socket.send_to(&buf, plugin.addr)?;
+                    }
+                }
+
+                metrics::plugin_traffic(
+                    &plugin.plugin.metadata.name,
+                    Direction::Incoming,
+                    buf.len(),
+                );
+            }
+
+            for peer in peers.iter_mut().filter(|peer| peer.plugin.is_none()) {
+                peer.send(&buf);
+            }
+        }
+
+        peers.retain(|peer| peer.connected);
+        drop(peers);
+
+        Ok(())
+    }
+
+    /// Sends to VRChat now, or once the bundle's timetag is due
+    ///
+    /// The encoded packet is sent as is when given
+    fn send_vrchat(&self, packet: OscPacket, buf: Option<&[u8]>, source: &str) -> Result<()> {
+        if let OscPacket::Bundle(bundle) = &packet {
+            if let Some(at) = schedule::due(bundle.timetag) {
+                self.schedule(at, Scheduled::Outgoing(packet, source.to_owned()), source);
+                return Ok(());
+            }
+        }
+
+        for message in messages(&packet) {
+            parameters::record(message);
+            metrics::address_traffic(message, Direction::Outgoing);
+            if message.addr == CHATBOX_INPUT {
+                if let Some(OscType::String(text)) = message.args.first() {
+                    *CHATBOX.lock().expect("Failed to lock the chatbox") = Some(text.clone());
+                }
+            }
+        }
+
+        let encoded;
+        let buf = if let Some(buf) = buf {
+            buf
+        } else {
+            encoded = rosc::encoder::encode(&packet)?;
+            &encoded
+        };
+
+        if let Some(recorder) = &self.recorder {
+            recorder.record(Direction::Outgoing, Some(source), buf);
+        }
+
+        self.socket.send_to(buf, &self.send_addr)?;
+
+        Ok(())
+    }
+
+    fn schedule(&self, at: SystemTime, scheduled: Scheduled, source: &str) {
+        if !self.scheduled.push(at, scheduled) {
+            tracing::warn!(
+                "Dropped a bundle from {source}, it's due more than {}s from now or too many are waiting",
+                schedule::MAX_DELAY.as_secs()
+            );
+        }
+    }
+
+    fn send_scheduled(&self) {
+        loop {
+            let result = match self.scheduled.next() {
+                Scheduled::Incoming(messages) => {
+                    let plugins = self.plugins.read().expect("Failed to read plugins").clone();
+                    self.deliver(&messages.iter().collect::<Vec<_>>(), &plugins)
+                }
+                Scheduled::Outgoing(packet, source) => self.send_vrchat(packet, None, &source),
+            };
+
+            if let Err(error) = result {
+                tracing::error!("Failed to send a scheduled bundle: {error}");
+            }
+        }
+    }
+
+    /// Relays packets a forward target sends to its listen address on to VRChat
+    fn relay(&self, forward: &Forward) {
+        let Some(socket) = &forward.socket else {
+            return;
+        };
+
+        let mut buf = vec![0u8; MAX_DATAGRAM];
+        loop {
+            let Ok(size) = socket.recv(&mut buf) else {
+                continue;
+            };
+
+            if let Err(error) = self.relay_packet(&buf[..size], &forward.name) {
+                tracing::error!("Failed to relay a packet from {}: {error}", forward.name);
+            }
+        }
+    }
+
+    /// Accepts TCP peers, what they send is relayed on to VRChat
+    fn accept(self: Arc<Self>) {
+        let Some((listener, framing)) = &self.tcp else {
+            return;
+        };
+
+        for stream in listener.incoming() {
+            let peer = stream
+                .map_err(anyhow::Error::from)
+                .and_then(|stream| {
+                    stream.set_write_timeout(Some(TCP_WRITE_TIMEOUT))?;
+                    OscStream::new(stream, *framing)
+                })
+                .and_then(|reader| Ok((TcpPeer::spawn(&reader)?, reader)));
+
+            let (peer, reader) = match peer {
+                Ok(peer) => peer,
+                Err(error) => {
+                    tracing::error!("Failed to accept a TCP peer: {error}");
+                    continue;
+                }
+            };
+
+            let addr = peer.addr;
+            self.tcp_peers
+                .lock()
+                .expect("Failed to lock the TCP peers")
+                .push(peer);
+
+            let router = self.clone();
+            std::thread::spawn(move || router.serve_tcp(reader, addr));
+        }
+    }
+
+    /// Relays what a TCP peer sends on to VRChat, like the plugin's own packets if it's a plugin
+    fn serve_tcp(&self, mut reader: OscStream, addr: SocketAddr) {
+        let name = format!("TCP peer {addr}");
+        let mut plugin = None;
+        loop {
+            let buf = match reader.recv_raw() {
+                Ok(Some(buf)) => buf,
+                Ok(None) => break, // Disconnected
+                Err(error) => {
+                    tracing::warn!("Disconnecting {name}: {error}");
+                    break;
+                }
+            };
+
+            if plugin.is_none() {
+                if let Some(port) = hello(&buf) {
+                    plugin = self.identify(addr, port);
+                    continue;
+                }
+            }
+
+            let result = plugin.and_then(|plugin| self.running(plugin)).map_or_else(
+                || self.relay_packet(&buf, &name),
+                |plugin| self.outgoing(&buf, &plugin),
+            );
+
+            if let Err(error) = result {
+                tracing::error!("Failed to relay a packet from {name}: {error}");
+            }
+        }
+    }
+
+    /// Attaches a TCP peer to the plugin it says it is, if that plugin is running on the same host
+    fn identify(&self, addr: SocketAddr, port: u16) -> Option<SocketAddr> {
+        let plugin_addr = SocketAddr::new(addr.ip(), port);
+        let Some(plugin) = self.running(plugin_addr) else {
+            tracing::warn!("TCP peer {addr} isn't a running plugin, sending it everything");
+            return None;
+        };
+
+        if let Some(peer) = self
+            .tcp_peers
+            .lock()
+            .expect("Failed to lock the TCP peers")
+            .iter_mut()
+            .find(|peer| peer.addr == addr)
+        {
+            peer.plugin = Some(plugin_addr);
+        }
+
+        tracing::debug!("TCP peer {addr} is {}", plugin.plugin.metadata.name);
+        Some(plugin_addr)
+    }
+
+    fn running(&self, addr: SocketAddr) -> Option<Arc<RunningPlugin>> {
+        self.plugins
+            .read()
+            .expect("Failed to read plugins")
+            .iter()
+            .find(|plugin| plugin.addr == addr)
+            .cloned()
+    }
+
+    fn relay_packet(&self, buf: &[u8], source: &str) -> Result<()> {
+        let Ok((_buf, packet)) = rosc::decoder::decode_udp(buf) else {
+            return Ok(()); // VRChat can't read it either
+        };
+
+        if !self.limiter.limit(&packet, source) {
+            return Ok(()); // Throttled
+        }
+
+        self.send_vrchat(packet, Some(buf), source)
+    }
+
+    /// Sends a packet to VRChat through the rate limits, for sources other than plugins
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if couldn't send the packet
+    pub fn send(&self, packet: OscPacket, source: &str) -> Result<()> {
+        if !self.limiter.limit(&packet, source) {
+            return Ok(()); // Throttled
+        }
+
+        self.send_vrchat(packet, None, source)
+    }
+
+    /// Sends rate limited packets once they have a token again
+    fn send_queued(&self) {
+        loop {
+            std::thread::sleep(rate_limit::TICK);
+
+            for (source, packet) in self.limiter.ready() {
+                if let Err(error) = self.send_vrchat(packet, None, &source) {
+                    tracing::error!("Failed to send a rate limited packet: {error}");
+                }
+            }
+        }
+    }
+
+    /// Feeds a recorded session's incoming packets to the plugins as if VRChat sent them
+    fn replay(&self) {
+        let Some((path, speed)) = &self.replay else {
+            return;
+        };
+
+        let result = session::replay(path, *speed, |record| {
+            if record.direction != Direction::Incoming {
+                return Ok(()); // The plugins send these again themselves
+            }
+
+            let plugins = self.plugins.read().expect("Failed to read plugins").clone();
+            self.incoming(&record.packet, &plugins)
+        });
+
+        match result {
+            Ok(()) => tracing::info!("Finished replaying {}", path.display()),
+            Err(error) => tracing::error!("Failed to replay {}: {error}", path.display()),
+        }
+    }
+}
+
+impl TcpPeer {
+    /// Starts the thread writing to the peer
+    fn spawn(stream: &OscStream) -> Result<Self> {
+        let mut writer = stream.try_clone()?;
+        let (queue, packets) = mpsc::sync_channel::<Vec<u8>>(TCP_QUEUE);
+        std::thread::spawn(move || {
+            for buf in packets {
+                if writer.send_raw(&buf).is_err() {
+                    let _ = writer.shutdown(); // Stops reading from it too
+                    break;
+                }
+            }
+        });
+
+        Ok(Self {
+            addr: stream.peer_addr()?,
+            plugin: None,
+            stream: stream.try_clone()?,
+            queue,
+            connected: true,
+        })
+    }
+
+    /// Queues the packet, peers that don't keep up or failed to write are disconnected
+    fn send(&mut self, buf: &[u8]) {
+        match self.queue.try_send(buf.to_vec()) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                tracing::warn!("Disconnecting TCP peer {}, it isn't keeping up", self.addr);
+                let _ = self.stream.shutdown();
+                self.connected = false;
+            }
+            Err(TrySendError::Disconnected(_)) => self.connected = false,
+        }
+    }
+}
+
+/// The UDP port a plugin sent in [`HELLO`] when connecting over TCP
+fn hello(buf: &[u8]) -> Option<u16> {
+    let Ok((_buf, OscPacket::Message(message))) = rosc::decoder::decode_udp(buf) else {
+        return None;
+    };
+
+    if message.addr != HELLO {
+        return None;
+    }
+
+    let port = message.args.first().cloned().and_then(OscType::int)?;
+    u16::try_from(port).ok()
+}
+
+/// Every message in the packet, including those inside bundles
+fn messages(packet: &OscPacket) -> Vec<&OscMessage> {
+    match packet {
+        OscPacket::Message(message) => vec![message],
+        OscPacket::Bundle(bundle) => bundle.content.iter().flat_map(messages).collect(),
+    }
+}
+
+/// Every message in the packet with when it's due, `None` for now
+///
+/// Nested bundles can't be delivered before the bundles containing them
+fn timed_messages(
+    packet: &OscPacket,
+    at: Option<SystemTime>,
+) -> Vec<(Option<SystemTime>, &OscMessage)> {
+    match packet {
+        OscPacket::Message(message) => vec![(at, message)],
+        OscPacket::Bundle(bundle) => {
+            let at = at.max(schedule::due(bundle.timetag));
+            bundle
+                .content
+                .iter()
+                .flat_map(|packet| timed_messages(packet, at))
+                .collect()
+        }
+    }
+}
@@ -0,0 +1,232 @@
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::BinaryHeap,
+    sync::{Condvar, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use rosc::OscTime;
+
+/// The timetag for bundles that should be delivered now
+pub const IMMEDIATELY: OscTime = OscTime {
+    seconds:    0,
+    fractional: 1,
+};
+
+/// Bundles due later than this are dropped instead of held back
+pub const MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// Bundles held back at once, anything past this is dropped
+pub const MAX_SCHEDULED: usize = 1024;
+
+/// When a bundle with this timetag should be delivered, `None` if it's due now
+#[must_use]
+pub fn due(timetag: OscTime) -> Option<SystemTime> {
+    if timetag.seconds == 0 {
+        return None; // Immediately
+    }
+
+    let at = SystemTime::from(timetag);
+    (at > SystemTime::now()).then_some(at)
+}
+
+struct Entry<T> {
+    at:   SystemTime,
+    /// Keeps items due at the same time in order
+    seq:  u64,
+    item: T,
+}
+
+impl<T> PartialEq for Entry<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T> Eq for Entry<T> {}
+
+impl<T> PartialOrd for Entry<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Entry<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.at, self.seq).cmp(&(other.at, other.seq))
+    }
+}
+
+struct Queue<T> {
+    seq:     u64,
+    entries: BinaryHeap<Reverse<Entry<T>>>,
+}
+
+/// Holds items back until they are due
+pub struct Schedule<T> {
+    queue:   Mutex<Queue<T>>,
+    changed: Condvar,
+}
+
+impl<T> Default for Schedule<T> {
+    fn default() -> Self {
+        Self {
+            queue:   Mutex::new(Queue {
+                seq:     0,
+                entries: BinaryHeap::new(),
+            }),
+            changed: Condvar::new(),
+        }
+    }
+}
+
+impl<T> Schedule<T> {
+    /// Returns `false` if the item was dropped, it's due after [`MAX_DELAY`] or [`MAX_SCHEDULED`] are waiting
+    ///
+    /// # Panics
+    ///
+    /// Will panic if the schedule lock was poisoned
+    pub fn push(&self, at: SystemTime, item: T) -> bool {
+        if at.duration_since(SystemTime::now()).unwrap_or_default() > MAX_DELAY {
+            return false;
+        }
+
+        let mut queue = self.queue.lock().expect("Failed to lock the schedule");
+        if queue.entries.len() >= MAX_SCHEDULED {
+            return false;
+        }
+
+        let seq = queue.seq;
+        queue.seq += 1;
+        queue.entries.push(Reverse(Entry { at, seq, item }));
+        drop(queue);
+
+        self.changed.notify_one();
+        true
+    }
+
+    /// Blocks until the next item is due
+    ///
+    /// # Panics
+    ///
+    /// Will panic if the schedule lock was poisoned
+    pub fn next(&self) -> T {
+        let mut queue = self.queue.lock().expect("Failed to lock the schedule");
+        loop {
+            let Some(Reverse(entry)) = queue.entries.peek() else {
+                queue = self
+                    .changed
+                    .wait(queue)
+                    .expect("Failed to lock the schedule");
+                continue;
+            };
+
+            match entry.at.duration_since(SystemTime::now()) {
+                Ok(timeout) if !timeout.is_zero() => {
+                    // Woken up early when an earlier item is pushed
+                    queue = self
+                        .changed
+                        .wait_timeout(queue, timeout)
+                        .expect("Failed to lock the schedule")
+                        .0;
+                }
+                _ => {
+                    let Reverse(entry) = queue.entries.pop().expect("The entry was just peeked");
+                    drop(queue);
+
+                    return entry.item;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    fn in_ms(ms: u64) -> SystemTime {
+        SystemTime::now() + Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn delivers_in_timetag_order() {
+        let schedule = Schedule::default();
+        assert!(schedule.push(in_ms(60), 3));
+        assert!(schedule.push(in_ms(20), 1));
+        assert!(schedule.push(in_ms(40), 2));
+
+        assert_eq!(
+            [schedule.next(), schedule.next(), schedule.next()],
+            [1, 2, 3]
+        );
+    }
+
+    #[test]
+    fn keeps_items_due_at_the_same_time_in_order() {
+        let schedule = Schedule::default();
+        let at = in_ms(20);
+        for item in 0..5 {
+            assert!(schedule.push(at, item));
+        }
+
+        assert_eq!(
+            (0..5).map(|_| schedule.next()).collect::<Vec<_>>(),
+            [0, 1, 2, 3, 4]
+        );
+    }
+
+    #[test]
+    fn holds_items_back_until_due() {
+        let schedule = Schedule::default();
+        let at = in_ms(100);
+        assert!(schedule.push(at, ()));
+
+        schedule.next();
+        assert!(SystemTime::now() >= at);
+    }
+
+    #[test]
+    fn wakes_up_for_earlier_items() {
+        let schedule = Arc::new(Schedule::default());
+        assert!(schedule.push(in_ms(10_000), "later"));
+
+        let next = std::thread::spawn({
+            let schedule = schedule.clone();
+            move || schedule.next()
+        });
+
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(schedule.push(in_ms(20), "sooner"));
+        assert_eq!(next.join().unwrap(), "sooner");
+    }
+
+    #[test]
+    fn drops_items_too_far_ahead() {
+        let schedule = Schedule::default();
+        assert!(!schedule.push(SystemTime::now() + MAX_DELAY * 2, ()));
+        assert!(schedule.push(SystemTime::now() + MAX_DELAY / 2, ()));
+    }
+
+    #[test]
+    fn drops_items_once_full() {
+        let schedule = Schedule::default();
+        let at = in_ms(1000);
+        for item in 0..MAX_SCHEDULED {
+            assert!(schedule.push(at, item));
+        }
+
+        assert!(!schedule.push(at, MAX_SCHEDULED));
+    }
+
+    #[test]
+    fn only_future_timetags_are_held_back() {
+        assert_eq!(due(IMMEDIATELY), None);
+        assert_eq!(due(OscTime::try_from(SystemTime::now()).unwrap()), None);
+
+        let later = SystemTime::now() + Duration::from_secs(1);
+        assert!(due(OscTime::try_from(later).unwrap()).is_some());
+    }
+}
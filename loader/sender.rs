@@ -0,0 +1,292 @@
+use std::{
+    collections::HashMap,
+    net::UdpSocket,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Result;
+use rosc::{OscBundle, OscMessage, OscPacket, OscType};
+
+use crate::{avatar::PARAMETERS_PREFIX, schedule::IMMEDIATELY};
+
+struct Sent {
+    value: OscType,
+    at:    SystemTime,
+}
+
+/// Sends avatar parameters only when their value changed, so plugins can set them as often as they like
+///
+/// Values are also resent when the loader saw VRChat report a different one, or after the refresh interval
+pub struct ParameterSender<'a> {
+    socket:  &'a UdpSocket,
+    /// Floats closer than this to the last sent value are treated as unchanged
+    epsilon: f32,
+    refresh: Option<Duration>,
+    sent:    HashMap<String, Sent>,
+}
+
+impl<'a> ParameterSender<'a> {
+    #[must_use]
+    pub fn new(socket: &'a UdpSocket) -> Self {
+        Self {
+            socket,
+            epsilon: 0.0,
+            refresh: None,
+            sent: HashMap::new(),
+        }
+    }
+
+    #[must_use]
+    pub const fn with_epsilon(mut self, epsilon: f32) -> Self {
+        self.epsilon = epsilon;
+        self
+    }
+
+    /// Resends unchanged values once they are older than the interval
+    #[must_use]
+    pub const fn with_refresh(mut self, interval: Duration) -> Self {
+        self.refresh = Some(interval);
+        self
+    }
+
+    /// Sends the parameter, by its name after `/avatar/parameters/`, if it changed
+    ///
+    /// Returns whether it was sent
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the packet couldn't be encoded or sent
+    pub fn set(&mut self, name: &str, value: impl Into<OscType>) -> Result<bool> {
+        let value = value.into();
+        if !self.is_changed(name, &value) {
+            return Ok(false);
+        }
+
+        self.send(vec![(name.to_owned(), value)])?;
+
+        Ok(true)
+    }
+
+    /// Sends the parameters that changed together in one bundle, so VRChat applies them at once
+    ///
+    /// Returns how many were sent
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the bundle couldn't be encoded or sent
+    pub fn set_all<N, V>(&mut self, parameters: impl IntoIterator<Item = (N, V)>) -> Result<usize>
+    where
+        N: AsRef<str>,
+        V: Into<OscType>,
+    {
+        let changed = parameters
+            .into_iter()
+            .map(|(name, value)| (name.as_ref().to_owned(), value.into()))
+            .filter(|(name, value)| self.is_changed(name, value))
+            .collect::<Vec<_>>();
+
+        let count = changed.len();
+        if count > 0 {
+            self.send(changed)?;
+        }
+
+        Ok(count)
+    }
+
+    /// Whether [`Self::set`] would send the value
+    #[must_use]
+    pub fn is_changed(&self, name: &str, value: &OscType) -> bool {
+        let Some(sent) = self.sent.get(name) else {
+            return true; // Never sent
+        };
+
+        if self
+            .refresh
+            .is_some_and(|refresh| sent.at.elapsed().unwrap_or_default() >= refresh)
+        {
+            return true;
+        }
+
+        if !self.is_same(&sent.value, value) {
+            return true;
+        }
+
+        // VRChat or another plugin may have changed it since, older values were routed before ours
+        let sent_at = sent
+            .at
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        crate::host::parameter(name).is_some_and(|current| {
+            u128::from(current.updated) > sent_at.as_millis()
+                && !self.is_same(&current.value.into(), value)
+        })
+    }
+
+    fn send(&mut self, parameters: Vec<(String, OscType)>) -> Result<()> {
+        let mut content = parameters
+            .iter()
+            .map(|(name, value)| {
+                OscPacket::Message(OscMessage {
+                    addr: format!("{PARAMETERS_PREFIX}{name}"),
+                    args: vec![value.clone()],
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let packet = if content.len() == 1 {
+            content.remove(0)
+        } else {
+            OscPacket::Bundle(OscBundle {
+                timetag: IMMEDIATELY,
+                content,
+            })
+        };
+
+        let msg_buf = rosc::encoder::encode(&packet)?;
+        self.socket.send(&msg_buf)?;
+
+        let at = SystemTime::now();
+        for (name, value) in parameters {
+            self.sent.insert(name, Sent { value, at });
+        }
+
+        Ok(())
+    }
+
+    /// Forgets what was sent so every parameter is sent again
+    pub fn clear(&mut self) {
+        self.sent.clear();
+    }
+
+    fn is_same(&self, previous: &OscType, value: &OscType) -> bool {
+        match (previous, value) {
+            (OscType::Float(previous), OscType::Float(value)) => {
+                (previous - value).abs() <= self.epsilon
+            }
+            _ => previous == value,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A sender and the socket it sends to
+    fn sockets() -> (UdpSocket, UdpSocket) {
+        let vrchat = UdpSocket::bind("127.0.0.1:0").unwrap();
+        vrchat
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+
+        let plugin = UdpSocket::bind("127.0.0.1:0").unwrap();
+        plugin.connect(vrchat.local_addr().unwrap()).unwrap();
+
+        (plugin, vrchat)
+    }
+
+    /// Every packet that arrived, waiting a bit for the last one
+    fn received(vrchat: &UdpSocket) -> Vec<OscPacket> {
+        let mut buf = [0u8; rosc::decoder::MTU];
+        let mut packets = Vec::new();
+        while let Ok(size) = vrchat.recv(&mut buf) {
+            packets.push(rosc::decoder::decode_udp(&buf[..size]).unwrap().1);
+        }
+
+        packets
+    }
+
+    fn parameter(name: &str, value: impl Into<OscType>) -> OscPacket {
+        OscPacket::Message(OscMessage {
+            addr: format!("{PARAMETERS_PREFIX}{name}"),
+            args: vec![value.into()],
+        })
+    }
+
+    #[test]
+    fn suppresses_unchanged_values() {
+        let (plugin, vrchat) = sockets();
+        let mut sender = ParameterSender::new(&plugin);
+
+        assert!(sender.set("VRCOSC/Media/Play", true).unwrap());
+        assert!(!sender.set("VRCOSC/Media/Play", true).unwrap());
+        assert!(!sender.is_changed("VRCOSC/Media/Play", &OscType::Bool(true)));
+
+        assert_eq!(received(&vrchat), [parameter("VRCOSC/Media/Play", true)]);
+    }
+
+    #[test]
+    fn sends_changed_values() {
+        let (plugin, vrchat) = sockets();
+        let mut sender = ParameterSender::new(&plugin);
+
+        assert!(sender.set("VRCOSC/Media/Play", true).unwrap());
+        assert!(sender.set("VRCOSC/Media/Play", false).unwrap());
+        assert!(sender.set("VRCOSC/Media/Volume", 0.5).unwrap());
+
+        assert_eq!(
+            received(&vrchat),
+            [
+                parameter("VRCOSC/Media/Play", true),
+                parameter("VRCOSC/Media/Play", false),
+                parameter("VRCOSC/Media/Volume", 0.5),
+            ]
+        );
+    }
+
+    #[test]
+    fn treats_floats_within_epsilon_as_unchanged() {
+        let (plugin, vrchat) = sockets();
+        let mut sender = ParameterSender::new(&plugin).with_epsilon(0.01);
+
+        assert!(sender.set("VRCOSC/Clock/Seconds", 0.5_f32).unwrap());
+        assert!(!sender.set("VRCOSC/Clock/Seconds", 0.505_f32).unwrap());
+        assert!(sender.set("VRCOSC/Clock/Seconds", 0.52_f32).unwrap());
+
+        assert_eq!(received(&vrchat).len(), 2);
+    }
+
+    #[test]
+    fn resends_after_the_refresh_interval_or_clearing() {
+        let (plugin, vrchat) = sockets();
+        let mut refreshed = ParameterSender::new(&plugin).with_refresh(Duration::ZERO);
+        assert!(refreshed.set("VRCOSC/Media/Play", true).unwrap());
+        assert!(refreshed.set("VRCOSC/Media/Play", true).unwrap());
+
+        let mut cleared = ParameterSender::new(&plugin);
+        assert!(cleared.set("VRCOSC/Media/Play", true).unwrap());
+        cleared.clear();
+        assert!(cleared.set("VRCOSC/Media/Play", true).unwrap());
+
+        assert_eq!(received(&vrchat).len(), 4);
+    }
+
+    #[test]
+    fn bundles_only_the_changed_values() {
+        let (plugin, vrchat) = sockets();
+        let mut sender = ParameterSender::new(&plugin);
+        sender.set("VRCOSC/Clock/Hours", 0.25).unwrap();
+        received(&vrchat);
+
+        let sent = sender
+            .set_all([
+                ("VRCOSC/Clock/Hours", 0.25),
+                ("VRCOSC/Clock/Minutes", 0.5),
+                ("VRCOSC/Clock/Seconds", 0.75),
+            ])
+            .unwrap();
+
+        assert_eq!(sent, 2);
+        assert_eq!(
+            received(&vrchat),
+            [OscPacket::Bundle(OscBundle {
+                timetag: IMMEDIATELY,
+                content: vec![
+                    parameter("VRCOSC/Clock/Minutes", 0.5),
+                    parameter("VRCOSC/Clock/Seconds", 0.75),
+                ],
+            })]
+        );
+        assert_eq!(sender.set_all([("VRCOSC/Clock/Minutes", 0.5)]).unwrap(), 0);
+    }
+}
@@ -0,0 +1,220 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, LineWriter, Write},
+    path::Path,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::{ensure, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "kebab-case")]
+pub enum Direction {
+    /// VRChat -> Plugins
+    Incoming,
+    /// Plugins -> VRChat
+    Outgoing,
+}
+
+/// One line of a session file
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Record {
+    /// Milliseconds since the recording started
+    pub time:      u64,
+    pub direction: Direction,
+    /// The plugin, forward target or TCP peer that sent it, `None` for VRChat
+    pub source:    Option<String>,
+    /// The encoded packet as base64
+    #[serde(serialize_with = "to_base64", deserialize_with = "from_base64")]
+    pub packet:    Vec<u8>,
+}
+
+fn to_base64<S: Serializer>(packet: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&STANDARD.encode(packet))
+}
+
+fn from_base64<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    let packet = String::deserialize(deserializer)?;
+    STANDARD.decode(packet).map_err(serde::de::Error::custom)
+}
+
+/// Slower replays would take days and overflow the delays between packets
+pub const MIN_SPEED: f64 = 0.01;
+
+/// Whether packets can be replayed at this speed, `0` or at least [`MIN_SPEED`]
+#[must_use]
+pub fn is_valid_speed(speed: f64) -> bool {
+    speed == 0.0 || (speed.is_finite() && speed >= MIN_SPEED)
+}
+
+/// Rejects invalid speeds when the config is read, see [`is_valid_speed`]
+///
+/// # Errors
+///
+/// Will return `Err` if the speed isn't a number or invalid
+pub fn deserialize_speed<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    let speed = f64::deserialize(deserializer)?;
+    if !is_valid_speed(speed) {
+        return Err(serde::de::Error::custom(format!(
+            "the replay speed must be 0 or at least {MIN_SPEED}, not {speed}"
+        )));
+    }
+
+    Ok(speed)
+}
+
+/// Writes every routed packet to a JSON Lines session file
+pub struct Recorder {
+    start: Instant,
+    file:  Mutex<LineWriter<File>>,
+}
+
+impl Recorder {
+    /// # Errors
+    ///
+    /// Will return `Err` if the file couldn't be created
+    pub fn create(path: &Path) -> Result<Self> {
+        let file =
+            File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+
+        Ok(Self {
+            start: Instant::now(),
+            file:  Mutex::new(LineWriter::new(file)),
+        })
+    }
+
+    /// Failures are logged, recording shouldn't stop routing
+    ///
+    /// # Panics
+    ///
+    /// Will panic if the file lock was poisoned
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn record(&self, direction: Direction, source: Option<&str>, packet: &[u8]) {
+        let record = Record {
+            time: self.start.elapsed().as_millis() as u64,
+            direction,
+            source: source.map(ToOwned::to_owned),
+            packet: packet.to_vec(),
+        };
+
+        let result = serde_json::to_string(&record)
+            .map_err(anyhow::Error::from)
+            .and_then(|line| {
+                let mut file = self.file.lock().expect("Failed to lock the session file");
+                Ok(writeln!(file, "{line}")?)
+            });
+
+        if let Err(error) = result {
+            tracing::error!("Failed to record a packet: {error}");
+        }
+    }
+}
+
+/// # Errors
+///
+/// Will return `Err` if the file couldn't be read or has an invalid line
+pub fn load(path: &Path) -> Result<Vec<Record>> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+
+    BufReader::new(file)
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| line.as_ref().map_or(true, |line| !line.trim().is_empty()))
+        .map(|(index, line)| {
+            serde_json::from_str(&line?)
+                .with_context(|| format!("Invalid record on line {}", index + 1))
+        })
+        .collect()
+}
+
+/// Calls `f` for every record at its original time divided by `speed`, or as fast as possible with `0`
+///
+/// # Errors
+///
+/// Will return `Err` if the speed is invalid, the file couldn't be loaded or `f` failed
+pub fn replay(path: &Path, speed: f64, mut f: impl FnMut(&Record) -> Result<()>) -> Result<()> {
+    ensure!(
+        is_valid_speed(speed),
+        "The replay speed must be 0 or at least {MIN_SPEED}, not {speed}"
+    );
+
+    let records = load(path)?;
+    let start = Instant::now();
+    for record in &records {
+        if speed > 0.0 {
+            let at = Duration::from_millis(record.time).div_f64(speed);
+            std::thread::sleep(at.saturating_sub(start.elapsed()));
+        }
+
+        f(record)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Config;
+
+    #[test]
+    fn replays_what_was_recorded_in_order() {
+        let path =
+            std::env::temp_dir().join(format!("vrc-osc-replay-{}.jsonl", std::process::id()));
+        let recorder = Recorder::create(&path).unwrap();
+        recorder.record(Direction::Incoming, None, b"/avatar/change");
+        std::thread::sleep(Duration::from_millis(50));
+        recorder.record(Direction::Outgoing, Some("Clock"), &[0xC0, 0xDB, 0x00]);
+        drop(recorder);
+
+        let start = Instant::now();
+        let mut replayed = Vec::new();
+        replay(&path, 2.0, |record| {
+            replayed.push(record.clone());
+            Ok(())
+        })
+        .unwrap();
+        let elapsed = start.elapsed();
+        let _ = std::fs::remove_file(&path);
+
+        let [vrchat, clock] = replayed.as_slice() else {
+            panic!("Expected 2 records, got {replayed:?}");
+        };
+
+        assert_eq!(vrchat.direction, Direction::Incoming);
+        assert_eq!(vrchat.source, None);
+        assert_eq!(vrchat.packet, b"/avatar/change");
+        assert_eq!(clock.direction, Direction::Outgoing);
+        assert_eq!(clock.source.as_deref(), Some("Clock"));
+        assert_eq!(clock.packet, [0xC0, 0xDB, 0x00]);
+
+        // Twice as fast as recorded
+        assert!(clock.time >= 50);
+        assert!(elapsed >= Duration::from_millis(clock.time / 2));
+        assert!(elapsed < Duration::from_millis(clock.time));
+    }
+
+    #[test]
+    fn rejects_speeds_that_cant_be_replayed() {
+        for speed in [0.0, MIN_SPEED, 1.0, 1000.0] {
+            assert!(is_valid_speed(speed), "{speed} was rejected");
+        }
+
+        for speed in [-1.0, 0.001, f64::NAN, f64::INFINITY, f64::MIN_POSITIVE] {
+            assert!(!is_valid_speed(speed), "{speed} was accepted");
+            let error = replay(Path::new("missing.jsonl"), speed, |_| Ok(())).unwrap_err();
+            assert!(error.to_string().contains("replay speed"), "{error}");
+        }
+    }
+
+    #[test]
+    fn config_rejects_invalid_speeds() {
+        assert!(toml::from_str::<Config>("replay_speed = 2.0").is_ok());
+        assert!(toml::from_str::<Config>("replay_speed = 0.001").is_err());
+        assert!(toml::from_str::<Config>("replay_speed = nan").is_err());
+        assert!(Config::default().set("replay_speed", "-inf").is_err());
+    }
+}
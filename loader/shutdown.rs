@@ -0,0 +1,113 @@
+//! Stops the loader on Ctrl-C or SIGTERM, saving its config and unloading every plugin before closing their libraries
+
+use std::{net::UdpSocket, path::Path, sync::Arc, time::Duration};
+
+use anyhow::Result;
+use tokio::task::JoinSet;
+
+use crate::{host, Config, RunningPlugins};
+
+/// How long plugins get to return from `load` before they're left running
+pub const STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Waits for Ctrl-C, or SIGTERM on Unix
+///
+/// # Errors
+///
+/// Will return `Err` if the signal handlers couldn't be installed
+pub async fn signal() -> Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = signal(SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result?,
+            _ = terminate.recv() => {}
+        }
+    }
+
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await?;
+
+    Ok(())
+}
+
+/// Saves the plugins enabled or disabled while the loader ran to its config file
+///
+/// Only `enabled` is taken from the running config, the environment and command line overrides are never saved
+///
+/// # Errors
+///
+/// Will return `Err` if the config file couldn't be written
+pub fn save_config(path: &Path) -> Result<()> {
+    let Some(running) = host::shared_config() else {
+        return Ok(()); // Never started
+    };
+
+    let mut config = Config::read(path).unwrap_or_default();
+    if config.enabled == running.enabled {
+        return Ok(()); // Unchanged
+    }
+
+    config.enabled = running.enabled;
+    config.write(path)
+}
+
+/// Unloads every plugin at once, then closes the libraries of those that stopped in reverse load order
+///
+/// Plugins are taken out of the list first so nothing is routed to them while they unload.
+/// Those that didn't stop within [`STOP_TIMEOUT`] are never closed, their threads still run code from the library.
+/// Returns `false` if any plugin was left running
+///
+/// # Panics
+///
+/// Will panic if the plugins lock was poisoned
+pub async fn stop_plugins(plugins: &RunningPlugins, loader_socket: &Arc<UdpSocket>) -> bool {
+    let running = std::mem::take(&mut *plugins.write().expect("Failed to write plugins"));
+
+    let mut tasks = JoinSet::new();
+    for (index, plugin) in running.into_iter().enumerate() {
+        let loader_socket = loader_socket.clone();
+        tasks.spawn(async move {
+            let result = plugin.stop(&loader_socket, STOP_TIMEOUT).await;
+            (index, plugin, result)
+        });
+    }
+
+    let mut stopped = Vec::new();
+    let mut all_stopped = true;
+    while let Some(joined) = tasks.join_next().await {
+        match joined {
+            Ok((index, plugin, Ok(()))) => stopped.push((index, plugin)),
+            Ok((_, plugin, Err(error))) => {
+                tracing::error!("{error:#}, leaving it loaded");
+                std::mem::forget(plugin);
+                all_stopped = false;
+            }
+            Err(error) => {
+                tracing::error!("Failed to stop a plugin: {error}");
+                all_stopped = false;
+            }
+        }
+    }
+
+    // Like destructors, the first plugin loaded is the last one closed
+    stopped.sort_by_key(|(index, _)| std::cmp::Reverse(*index));
+    for (_, running) in stopped {
+        let name = running.plugin.metadata.name.clone();
+
+        // Chats or the router may still hold it, it's closed when they let go instead
+        let closed = Arc::try_unwrap(running)
+            .ok()
+            .and_then(|running| Arc::try_unwrap(running.plugin).ok());
+        if let Some(plugin) = closed {
+            drop(plugin);
+            tracing::info!("Closed {name}");
+        } else {
+            tracing::warn!("{name} is still in use, it's closed once released");
+        }
+    }
+
+    all_stopped
+}
@@ -0,0 +1,396 @@
+use std::{
+    net::{SocketAddr, UdpSocket},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use tokio::{sync::Notify, task::JoinHandle};
+use tracing::Instrument;
+
+use crate::{logging, metrics, plugin::Plugin, validation::ValidationCounters};
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartMode {
+    Never,
+    #[default]
+    OnFailure,
+    /// Also restarts plugins that returned successfully, not suitable for setup-only plugins
+    Always,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RestartPolicy {
+    pub mode: RestartMode,
+    pub initial_backoff: u64,
+    pub max_backoff: u64,
+    /// Restarts in a row, plugins that stay up for `max_backoff` get them all back
+    pub max_restarts: u32,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self {
+            mode: RestartMode::default(),
+            initial_backoff: 1000,
+            max_backoff: 60_000,
+            max_restarts: 5,
+        }
+    }
+}
+
+/// What the supervisor last saw the plugin do
+#[derive(Clone, Copy, Debug, Default, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Health {
+    #[default]
+    Running,
+    /// Waiting out the backoff before restarting
+    Restarting,
+    /// Returned from `load` and wasn't restarted
+    Exited,
+    /// Failed or panicked and wasn't restarted
+    Failed,
+    Unloaded,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Status {
+    pub health:   Health,
+    pub restarts: u32,
+    /// The last error or panic
+    pub error:    Option<String>,
+}
+
+/// A supervised plugin, see [`spawn`]
+pub struct RunningPlugin {
+    pub plugin:     Arc<Plugin>,
+    /// The address the plugin sends from and receives on
+    pub addr:       SocketAddr,
+    pub validation: ValidationCounters,
+    status:         Arc<Mutex<Status>>,
+    stopping:       Arc<AtomicBool>,
+    /// Cuts the backoff short when stopping
+    stopped:        Arc<Notify>,
+    task:           Mutex<Option<JoinHandle<()>>>,
+}
+
+impl RunningPlugin {
+    /// # Panics
+    ///
+    /// Will panic if the status lock was poisoned
+    #[must_use]
+    pub fn status(&self) -> Status {
+        self.status
+            .lock()
+            .expect("Failed to lock the status")
+            .clone()
+    }
+
+    /// The plugin and its status, as the dashboard and control socket show it
+    #[must_use]
+    pub fn describe(&self) -> serde_json::Value {
+        serde_json::json!({
+            "filename": self.plugin.filename,
+            "name": self.plugin.metadata.name,
+            "version": self.plugin.metadata.version,
+            "status": self.status(),
+            "validation": self.validation,
+        })
+    }
+
+    /// Asks the plugin to unload and waits for its `load` to return without restarting it
+    ///
+    /// Once this returns `Ok` nothing runs the plugin's code anymore, so its library can be closed.
+    /// Stopping again waits for a plugin that didn't stop in time
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the wake up packet couldn't be sent, the plugin didn't stop in time,
+    /// or its chats are still running, in which case its library must stay open
+    ///
+    /// # Panics
+    ///
+    /// Will panic if the task lock was poisoned
+    pub async fn stop(&self, loader_socket: &UdpSocket, timeout: Duration) -> Result<()> {
+        let name = &self.plugin.metadata.name;
+        let task = self.task.lock().expect("Failed to lock the task").take();
+
+        self.stopping.store(true, Ordering::SeqCst);
+        self.stopped.notify_one();
+        let chats_finished = self.plugin.unload();
+
+        if let Some(mut task) = task {
+            // Wake up plugins blocked on receiving
+            let woken = loader_socket.send_to(&[], self.addr);
+            let stopped = match woken {
+                Ok(_) => tokio::time::timeout(timeout, &mut task).await.is_ok(),
+                Err(_) => false,
+            };
+
+            if !stopped {
+                *self.task.lock().expect("Failed to lock the task") = Some(task);
+                woken?;
+                bail!("{name} didn't unload within {timeout:?}");
+            }
+        }
+
+        if !chats_finished {
+            bail!("{name}'s chats didn't finish when it unloaded");
+        }
+
+        Ok(())
+    }
+}
+
+/// Runs the plugin's `load` on a blocking thread, logging how it exits and restarting it by policy
+///
+/// The plugin gets a clone of the socket on every run so its address stays the same for routing
+///
+/// # Errors
+///
+/// Will return `Err` if couldn't get the socket address
+pub fn spawn(
+    plugin: Arc<Plugin>,
+    socket: UdpSocket,
+    policy: RestartPolicy,
+) -> Result<RunningPlugin> {
+    let addr = socket.local_addr()?;
+    let status = Arc::new(Mutex::new(Status::default()));
+    let stopping = Arc::new(AtomicBool::new(false));
+    let stopped = Arc::new(Notify::new());
+    let span = logging::plugin_span(&plugin.metadata.name);
+    let supervisor = supervise(
+        plugin.clone(),
+        socket,
+        policy,
+        status.clone(),
+        stopping.clone(),
+        stopped.clone(),
+    );
+
+    let task = tokio::spawn(supervisor.instrument(span));
+
+    Ok(RunningPlugin {
+        plugin,
+        addr,
+        validation: ValidationCounters::default(),
+        status,
+        stopping,
+        stopped,
+        task: Mutex::new(Some(task)),
+    })
+}
+
+/// The restart budget and backoff, both reset once the plugin stays up for `max_backoff`
+struct Restarts {
+    policy:  RestartPolicy,
+    backoff: u64,
+    /// Restarts since the plugin last stayed up
+    recent:  u32,
+}
+
+impl Restarts {
+    const fn new(policy: RestartPolicy) -> Self {
+        Self {
+            policy,
+            backoff: policy.initial_backoff,
+            recent: 0,
+        }
+    }
+
+    /// Whether the policy restarts a plugin that exited or failed
+    const fn wanted(&self, failed: bool) -> bool {
+        match self.policy.mode {
+            RestartMode::Never => false,
+            RestartMode::OnFailure => failed,
+            RestartMode::Always => true,
+        }
+    }
+
+    /// How long to wait before restarting a plugin that ran for `uptime`, `None` once the budget is used
+    fn next(&mut self, uptime: Duration) -> Option<Duration> {
+        // A plugin that ran for a while isn't crash looping
+        if uptime > Duration::from_millis(self.policy.max_backoff) {
+            self.backoff = self.policy.initial_backoff;
+            self.recent = 0;
+        }
+
+        if self.recent >= self.policy.max_restarts {
+            return None;
+        }
+
+        let backoff = self.backoff;
+        self.backoff = self.backoff.saturating_mul(2).min(self.policy.max_backoff);
+        self.recent += 1;
+
+        Some(Duration::from_millis(backoff))
+    }
+}
+
+async fn supervise(
+    plugin: Arc<Plugin>,
+    socket: UdpSocket,
+    policy: RestartPolicy,
+    status: Arc<Mutex<Status>>,
+    stopping: Arc<AtomicBool>,
+    stopped: Arc<Notify>,
+) {
+    let mut restarts = Restarts::new(policy);
+
+    loop {
+        // Stopped while waiting to restart
+        if stopping.load(Ordering::SeqCst) {
+            tracing::info!("Unloaded");
+            update(&status, |status| status.health = Health::Unloaded);
+            break;
+        }
+
+        let socket = match socket.try_clone() {
+            Ok(socket) => socket,
+            Err(error) => {
+                tracing::error!("Failed to clone the socket: {error}");
+                break;
+            }
+        };
+
+        let started = Instant::now();
+        let running = plugin.clone();
+        update(&status, |status| status.health = Health::Running);
+        let result = tokio::task::spawn_blocking(move || running.load(socket)).await;
+        if stopping.load(Ordering::SeqCst) {
+            tracing::info!("Unloaded");
+            update(&status, |status| status.health = Health::Unloaded);
+            break;
+        }
+
+        let error = match result {
+            Ok(Ok(())) => {
+                tracing::info!("Exited");
+                None
+            }
+            Ok(Err(error)) => Some(format!("{error:#}")),
+            Err(error) => Some(format!("Panicked: {error}")),
+        };
+
+        if let Some(error) = &error {
+            tracing::error!("{error}");
+        }
+
+        let failed = error.is_some();
+        update(&status, |status| {
+            status.health = if failed {
+                Health::Failed
+            } else {
+                Health::Exited
+            };
+            if failed {
+                status.error = error;
+            }
+        });
+
+        if !restarts.wanted(failed) {
+            break;
+        }
+
+        let Some(backoff) = restarts.next(started.elapsed()) else {
+            tracing::error!("Used all {} restarts, giving up", policy.max_restarts);
+            break;
+        };
+
+        metrics::plugin_restarted(&plugin.metadata.name);
+        update(&status, |status| {
+            status.health = Health::Restarting;
+            status.restarts += 1;
+        });
+
+        tracing::warn!(
+            "Restarting in {}ms ({}/{})",
+            backoff.as_millis(),
+            restarts.recent,
+            policy.max_restarts
+        );
+
+        tokio::select! {
+            () = tokio::time::sleep(backoff) => {}
+            () = stopped.notified() => {} // Checked at the top
+        }
+    }
+}
+
+fn update(status: &Mutex<Status>, update: impl FnOnce(&mut Status)) {
+    update(&mut status.lock().expect("Failed to lock the status"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY: RestartPolicy = RestartPolicy {
+        mode: RestartMode::OnFailure,
+        initial_backoff: 100,
+        max_backoff: 1000,
+        max_restarts: 5,
+    };
+
+    const CRASHED: Duration = Duration::ZERO;
+    const STAYED_UP: Duration = Duration::from_secs(2);
+
+    fn backoffs(restarts: &mut Restarts, count: usize) -> Vec<Option<u128>> {
+        (0..count)
+            .map(|_| restarts.next(CRASHED).map(|backoff| backoff.as_millis()))
+            .collect()
+    }
+
+    #[test]
+    fn restarts_by_mode() {
+        for (mode, exited, failed) in [
+            (RestartMode::Never, false, false),
+            (RestartMode::OnFailure, false, true),
+            (RestartMode::Always, true, true),
+        ] {
+            let restarts = Restarts::new(RestartPolicy { mode, ..POLICY });
+            assert_eq!(restarts.wanted(false), exited, "{mode:?} after exiting");
+            assert_eq!(restarts.wanted(true), failed, "{mode:?} after failing");
+        }
+    }
+
+    #[test]
+    fn doubles_the_backoff_up_to_the_max() {
+        let mut restarts = Restarts::new(POLICY);
+        assert_eq!(
+            backoffs(&mut restarts, 5),
+            [Some(100), Some(200), Some(400), Some(800), Some(1000)]
+        );
+    }
+
+    #[test]
+    fn gives_up_once_the_budget_is_used() {
+        let mut restarts = Restarts::new(RestartPolicy {
+            max_restarts: 2,
+            ..POLICY
+        });
+
+        assert_eq!(backoffs(&mut restarts, 3), [Some(100), Some(200), None]);
+        assert_eq!(restarts.next(CRASHED), None);
+    }
+
+    #[test]
+    fn resets_after_staying_up() {
+        let mut restarts = Restarts::new(RestartPolicy {
+            max_restarts: 2,
+            ..POLICY
+        });
+
+        backoffs(&mut restarts, 2);
+        assert_eq!(restarts.next(STAYED_UP), Some(Duration::from_millis(100)));
+        assert_eq!(backoffs(&mut restarts, 2), [Some(200), None]);
+    }
+}
@@ -0,0 +1,235 @@
+use std::{
+    io::{BufRead, BufReader, ErrorKind, Read, Write},
+    net::{Shutdown, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket},
+};
+
+use anyhow::{bail, Result};
+use rosc::{OscMessage, OscPacket, OscType};
+use serde::{Deserialize, Serialize};
+
+/// SLIP special bytes from RFC 1055
+const END: u8 = 0xC0;
+const ESC: u8 = 0xDB;
+const ESC_END: u8 = 0xDC;
+const ESC_ESC: u8 = 0xDD;
+
+/// Frames bigger than this are rejected instead of allocated
+pub const MAX_FRAME: usize = 1 << 20;
+
+/// Sent first by plugins connecting to the loader, with the port of the UDP socket it gave them
+pub const HELLO: &str = "/vrc-osc/plugin";
+
+/// How OSC packets are delimited on a TCP stream
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Framing {
+    /// OSC 1.0, each packet is prefixed with its size as a big endian `int32`
+    LengthPrefix,
+    /// OSC 1.1, each packet is SLIP encoded between `END` bytes
+    #[default]
+    Slip,
+}
+
+impl Framing {
+    #[must_use]
+    pub fn encode(self, packet: &[u8]) -> Vec<u8> {
+        match self {
+            Self::LengthPrefix => {
+                let size = u32::try_from(packet.len()).unwrap_or(u32::MAX);
+                [&size.to_be_bytes(), packet].concat()
+            }
+            Self::Slip => {
+                let mut frame = Vec::with_capacity(packet.len() + 2);
+                frame.push(END);
+                for &byte in packet {
+                    match byte {
+                        END => frame.extend([ESC, ESC_END]),
+                        ESC => frame.extend([ESC, ESC_ESC]),
+                        _ => frame.push(byte),
+                    }
+                }
+                frame.push(END);
+                frame
+            }
+        }
+    }
+
+    /// Reads the next packet, returns `None` once the stream is closed
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the stream failed or the frame is invalid or too big
+    pub fn read(self, reader: &mut impl BufRead) -> Result<Option<Vec<u8>>> {
+        match self {
+            Self::LengthPrefix => read_length_prefixed(reader),
+            Self::Slip => read_slip(reader),
+        }
+    }
+}
+
+fn read_length_prefixed(reader: &mut impl BufRead) -> Result<Option<Vec<u8>>> {
+    let mut size = [0u8; 4];
+    match reader.read_exact(&mut size) {
+        Err(error) if error.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        result => result?,
+    }
+
+    let size = u32::from_be_bytes(size) as usize;
+    if size > MAX_FRAME {
+        bail!("The frame is {size} bytes, more than {MAX_FRAME}");
+    }
+
+    let mut packet = vec![0u8; size];
+    reader.read_exact(&mut packet)?;
+
+    Ok(Some(packet))
+}
+
+fn read_slip(reader: &mut impl BufRead) -> Result<Option<Vec<u8>>> {
+    loop {
+        let mut frame = Vec::new();
+        let limit = MAX_FRAME as u64 * 2 + 1; // Every byte could be escaped
+        let size = reader.by_ref().take(limit).read_until(END, &mut frame)?;
+
+        match frame.pop() {
+            None => return Ok(None),
+            Some(END) if frame.is_empty() => continue, // Between two frames
+            Some(END) => return unescape(&frame).map(Some),
+            Some(_) if size as u64 == limit => bail!("The frame is more than {MAX_FRAME} bytes"),
+            Some(_) => bail!("The stream closed in the middle of a frame"),
+        }
+    }
+}
+
+fn unescape(frame: &[u8]) -> Result<Vec<u8>> {
+    let mut packet = Vec::with_capacity(frame.len());
+    let mut bytes = frame.iter();
+    while let Some(&byte) = bytes.next() {
+        if byte != ESC {
+            packet.push(byte);
+            continue;
+        }
+
+        packet.push(match bytes.next() {
+            Some(&ESC_END) => END,
+            Some(&ESC_ESC) => ESC,
+            _ => bail!("Invalid SLIP escape"),
+        });
+    }
+
+    Ok(packet)
+}
+
+/// An OSC connection over TCP, to the loader's `tcp_addr` or any other peer
+pub struct OscStream {
+    reader:  BufReader<TcpStream>,
+    writer:  TcpStream,
+    framing: Framing,
+}
+
+impl OscStream {
+    /// # Errors
+    ///
+    /// Will return `Err` if the stream couldn't be cloned
+    pub fn new(stream: TcpStream, framing: Framing) -> Result<Self> {
+        Ok(Self {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+            framing,
+        })
+    }
+
+    /// # Errors
+    ///
+    /// Will return `Err` if couldn't connect
+    pub fn connect(addr: impl ToSocketAddrs, framing: Framing) -> Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+
+        Self::new(stream, framing)
+    }
+
+    /// Connects to the loader's `tcp_addr` as the plugin it gave `socket` to, so the connection gets
+    /// the plugin's subscriptions and validation instead of everything VRChat sends
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if couldn't connect
+    pub fn connect_plugin(
+        addr: impl ToSocketAddrs,
+        framing: Framing,
+        socket: &UdpSocket,
+    ) -> Result<Self> {
+        let mut stream = Self::connect(addr, framing)?;
+        stream.send(&OscPacket::Message(OscMessage {
+            addr: HELLO.into(),
+            args: vec![OscType::Int(i32::from(socket.local_addr()?.port()))],
+        }))?;
+
+        Ok(stream)
+    }
+
+    /// # Errors
+    ///
+    /// Will return `Err` if the stream couldn't be cloned
+    pub fn try_clone(&self) -> Result<Self> {
+        Self::new(self.writer.try_clone()?, self.framing)
+    }
+
+    /// Closes both directions, unblocking anything reading from or writing to the stream
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the stream is no longer connected
+    pub fn shutdown(&self) -> Result<()> {
+        Ok(self.writer.shutdown(Shutdown::Both)?)
+    }
+
+    /// # Errors
+    ///
+    /// Will return `Err` if the stream is no longer connected
+    pub fn peer_addr(&self) -> Result<SocketAddr> {
+        Ok(self.writer.peer_addr()?)
+    }
+
+    /// # Errors
+    ///
+    /// Will return `Err` if couldn't encode or send the packet
+    pub fn send(&mut self, packet: &OscPacket) -> Result<()> {
+        let buf = rosc::encoder::encode(packet)?;
+        self.send_raw(&buf)
+    }
+
+    /// Sends an already encoded packet
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if couldn't send the packet
+    pub fn send_raw(&mut self, buf: &[u8]) -> Result<()> {
+        self.writer.write_all(&self.framing.encode(buf))?;
+        Ok(())
+    }
+
+    /// Receives the next packet, returns `None` once the peer disconnected
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the stream failed or the packet is invalid
+    pub fn recv(&mut self) -> Result<Option<OscPacket>> {
+        let Some(buf) = self.recv_raw()? else {
+            return Ok(None);
+        };
+
+        let (_buf, packet) = rosc::decoder::decode_udp(&buf)?;
+        Ok(Some(packet))
+    }
+
+    /// Receives the next packet without decoding it
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the stream failed or the frame is invalid
+    pub fn recv_raw(&mut self) -> Result<Option<Vec<u8>>> {
+        self.framing.read(&mut self.reader)
+    }
+}
@@ -0,0 +1,302 @@
+//! Runs the router with real plugins against a fake VRChat, for end-to-end tests
+//!
+//! ```ignore
+//! let harness = Harness::start(&[testing::plugin_path("clock")?])?;
+//! let hours = harness.expect_message("/avatar/parameters/VRCOSC/Clock/Hours", TIMEOUT)?;
+//! ```
+
+use std::{
+    net::{SocketAddr, UdpSocket},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+        RwLock,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Result};
+use rosc::{OscMessage, OscPacket, OscType};
+use tokio::runtime::Runtime;
+
+use crate::{
+    avatar::{self, Avatar, AVATAR_CHANGE, PARAMETERS_PREFIX},
+    host,
+    parameters,
+    pattern::Pattern,
+    plugin::Plugin,
+    reload,
+    router::Router,
+    shutdown,
+    Config,
+    RunningPlugins,
+};
+
+/// A plugin library, by its `[lib]` name, built into the same target directory as the running test
+///
+/// Plugins are built with `cargo build --workspace`
+///
+/// # Errors
+///
+/// Will return `Err` if the plugin hasn't been built
+pub fn plugin_path(name: &str) -> Result<PathBuf> {
+    let filename = format!(
+        "{}{name}{}",
+        std::env::consts::DLL_PREFIX,
+        std::env::consts::DLL_SUFFIX
+    );
+
+    // Tests run from `target/<profile>/deps`, libraries are copied up to `target/<profile>`
+    let current_exe = std::env::current_exe()?;
+    current_exe
+        .ancestors()
+        .skip(1)
+        .take(2)
+        .map(|dir| dir.join(&filename))
+        .find(|path| path.exists())
+        .with_context(|| format!("{filename} isn't built, run `cargo build -p plugin-{name}`"))
+}
+
+/// Plugins keep their state in statics, so every harness opens its own copy of the library
+fn open_copy(filename: &str, path: &Path) -> Result<Plugin> {
+    static COPIES: AtomicU64 = AtomicU64::new(0);
+
+    let stem = path.file_stem().context("None")?.to_string_lossy();
+    let extension = path.extension().context("None")?.to_string_lossy();
+    let copy = COPIES.fetch_add(1, Ordering::Relaxed);
+    let dir = std::env::temp_dir().join("vrc-osc-testing");
+    let copy_path = dir.join(format!("{stem}-{}-{copy}.{extension}", std::process::id()));
+
+    std::fs::create_dir_all(&dir)?;
+    std::fs::copy(path, &copy_path)?;
+    let plugin = Plugin::with_path(filename, &copy_path);
+
+    // Stays mapped once opened, so test runs don't pile up copies
+    let _ = std::fs::remove_file(&copy_path);
+
+    plugin
+}
+
+/// A fake VRChat connected to a router running the chosen plugins
+///
+/// The loader's avatar and parameter state is global, so tests sharing it shouldn't run in parallel.
+/// Set the avatar with [`avatar::set_current`] before starting plugins that check it when loading
+pub struct Harness {
+    runtime:       Runtime,
+    vrchat:        UdpSocket,
+    loader_socket: Arc<UdpSocket>,
+    config:        Config,
+    pub plugins:   RunningPlugins,
+}
+
+impl Harness {
+    /// Starts the plugins, by their library path, with the default config
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if a plugin couldn't be opened or the router couldn't start
+    pub fn start(plugins: &[PathBuf]) -> Result<Self> {
+        Self::with_config(Config::default(), plugins)
+    }
+
+    /// Starts the plugins with the config, its addresses are replaced with local ones
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if a plugin couldn't be opened or the router couldn't start
+    pub fn with_config(mut config: Config, plugins: &[PathBuf]) -> Result<Self> {
+        let runtime = Runtime::new()?;
+        let _guard = runtime.enter();
+
+        let vrchat = UdpSocket::bind("127.0.0.1:0")?;
+        let loader_socket = Arc::new(UdpSocket::bind("127.0.0.1:0")?);
+        let loader_addr = loader_socket.local_addr()?;
+        vrchat.connect(loader_addr)?;
+
+        config.bind_addr = loader_addr.to_string();
+        config.send_addr = vrchat.local_addr()?.to_string();
+        parameters::reset(); // Left over from previous tests
+        host::set_config(&config);
+
+        let mut running = Vec::new();
+        for path in plugins {
+            let filename = path.file_name().context("None")?.to_string_lossy();
+            let plugin = open_copy(&filename, path)?;
+            running.push(Arc::new(crate::start_plugin(plugin, &config)?));
+        }
+
+        let plugins = Arc::new(RwLock::new(running));
+        host::set_plugins(&plugins);
+        let router = Router::new(loader_socket.clone(), plugins.clone(), &config)?;
+        std::thread::spawn(move || Arc::new(router).run());
+
+        Ok(Self {
+            runtime,
+            vrchat,
+            loader_socket,
+            config,
+            plugins,
+        })
+    }
+
+    /// Saves a running plugin's config and reloads it from a new copy, like the dashboard does
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the plugin isn't running, doesn't have a config or couldn't be reloaded
+    ///
+    /// # Panics
+    ///
+    /// Will panic if the plugins lock was poisoned
+    pub fn save_config(&self, path: &Path, config: &serde_json::Value) -> Result<()> {
+        let filename = path.file_name().context("None")?.to_string_lossy();
+        let running = self
+            .plugins
+            .read()
+            .expect("Failed to read plugins")
+            .iter()
+            .find(|running| running.plugin.filename == filename)
+            .cloned()
+            .with_context(|| format!("{filename} isn't running"))?;
+
+        running
+            .plugin
+            .set_config(config)
+            .with_context(|| format!("{filename} doesn't have a config"))??;
+        drop(running);
+
+        let plugin = open_copy(&filename, path)?;
+        self.runtime.block_on(reload::swap(
+            plugin,
+            &self.plugins,
+            &self.loader_socket,
+            &self.config,
+        ))
+    }
+
+    /// The address the fake VRChat sends from and receives on
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the socket isn't bound
+    pub fn vrchat_addr(&self) -> Result<SocketAddr> {
+        Ok(self.vrchat.local_addr()?)
+    }
+
+    /// Sends the packet to the loader as VRChat
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if couldn't encode or send the packet
+    pub fn send(&self, packet: &OscPacket) -> Result<()> {
+        let buf = rosc::encoder::encode(packet)?;
+        self.vrchat.send(&buf)?;
+
+        Ok(())
+    }
+
+    /// Sends an avatar parameter, by its name after `/avatar/parameters/`, as VRChat
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if couldn't encode or send the packet
+    pub fn send_parameter(&self, name: &str, value: impl Into<OscType>) -> Result<()> {
+        self.send(&OscPacket::Message(OscMessage {
+            addr: format!("{PARAMETERS_PREFIX}{name}"),
+            args: vec![value.into()],
+        }))
+    }
+
+    /// Switches to the avatar as if VRChat discovered it, and sends the avatar change
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if couldn't send the avatar change
+    pub fn change_avatar(&self, avatar: Avatar) -> Result<()> {
+        let id = avatar.id.clone().unwrap_or_default();
+        avatar::set_current(avatar);
+
+        self.send(&OscPacket::Message(OscMessage {
+            addr: AVATAR_CHANGE.into(),
+            args: vec![OscType::String(id)],
+        }))
+    }
+
+    /// The next packet the plugins sent to VRChat
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if nothing was sent within the timeout or the packet is invalid
+    pub fn recv(&self, timeout: Duration) -> Result<OscPacket> {
+        let mut buf = vec![0u8; u16::MAX.into()];
+        self.vrchat.set_read_timeout(Some(timeout))?;
+        let size = self
+            .vrchat
+            .recv(&mut buf)
+            .with_context(|| format!("Nothing was sent within {timeout:?}"))?;
+
+        let (_buf, packet) = rosc::decoder::decode_udp(&buf[..size])?;
+        Ok(packet)
+    }
+
+    /// Every message the plugins sent to VRChat within the duration, with bundles unpacked
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if a packet is invalid
+    pub fn messages(&self, duration: Duration) -> Result<Vec<OscMessage>> {
+        let deadline = Instant::now() + duration;
+        let mut messages = Vec::new();
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Ok(messages);
+            }
+
+            match self.recv(remaining) {
+                Ok(packet) => messages.extend(unpack(packet)),
+                Err(error) if error.is::<std::io::Error>() => return Ok(messages), // Timed out
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    /// Waits for the plugins to send a message matching the pattern, skipping any others
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the pattern is invalid or no message matched within the timeout
+    pub fn expect_message(&self, pattern: &str, timeout: Duration) -> Result<OscMessage> {
+        let pattern = Pattern::new(pattern)?;
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                bail!("Nothing matching {pattern} was sent within {timeout:?}");
+            }
+
+            let packet = self.recv(remaining)?;
+            if let Some(message) = unpack(packet)
+                .into_iter()
+                .find(|message| pattern.matches(&message.addr))
+            {
+                return Ok(message);
+            }
+        }
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        self.runtime
+            .block_on(shutdown::stop_plugins(&self.plugins, &self.loader_socket));
+    }
+}
+
+fn unpack(packet: OscPacket) -> Vec<OscMessage> {
+    match packet {
+        OscPacket::Message(message) => vec![message],
+        OscPacket::Bundle(bundle) => bundle.content.into_iter().flat_map(unpack).collect(),
+    }
+}
@@ -0,0 +1,74 @@
+use std::path::{Path, PathBuf};
+
+use loader::{
+    avatar::{Avatar, ParameterType},
+    avatar_config,
+};
+
+const CLOCK_AVATAR: &str = "avtr_11111111-1111-1111-1111-111111111111";
+const BROKEN_AVATAR: &str = "avtr_22222222-2222-2222-2222-222222222222";
+
+fn osc_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/OSC")
+}
+
+#[test]
+fn finds_avatar_by_id() {
+    let config = avatar_config::find(&osc_dir(), CLOCK_AVATAR).unwrap();
+
+    assert_eq!(config.id, CLOCK_AVATAR);
+    assert_eq!(config.name, "Clock Avatar");
+    assert_eq!(config.parameters.len(), 4);
+}
+
+#[test]
+fn parses_input_and_output_endpoints() {
+    let config = avatar_config::find(&osc_dir(), CLOCK_AVATAR).unwrap();
+    let hours = &config.parameters[0];
+    let velocity = &config.parameters[2];
+
+    let input = hours.input.as_ref().unwrap();
+    assert_eq!(input.address, "/avatar/parameters/VRCOSC/Clock/Hours");
+    assert_eq!(input.kind, ParameterType::Float);
+
+    assert!(velocity.input.is_none());
+    assert_eq!(velocity.output.as_ref().unwrap().kind, ParameterType::Float);
+}
+
+#[test]
+fn maps_to_avatar_parameters() {
+    let config = avatar_config::find(&osc_dir(), CLOCK_AVATAR).unwrap();
+    let avatar = Avatar::from(config);
+
+    assert_eq!(avatar.id.as_deref(), Some(CLOCK_AVATAR));
+    assert_eq!(avatar.parameters.len(), 4);
+    assert_eq!(
+        avatar.parameter("VRCOSC/Media/Play").unwrap().kind,
+        ParameterType::Bool
+    );
+    assert_eq!(
+        avatar.parameter("GestureLeft").unwrap().kind,
+        ParameterType::Int
+    );
+    assert!(avatar.parameter("VRCOSC/Clock/Minutes").is_none());
+}
+
+#[test]
+fn rejects_unknown_parameter_types() {
+    assert!(avatar_config::find(&osc_dir(), BROKEN_AVATAR).is_err());
+}
+
+#[test]
+fn missing_avatar_is_an_error() {
+    assert!(avatar_config::find(&osc_dir(), "avtr_missing").is_err());
+}
+
+#[test]
+fn override_is_used_as_is() {
+    let osc_dir = osc_dir();
+
+    assert_eq!(
+        avatar_config::osc_dir(osc_dir.to_str()),
+        Some(osc_dir.clone())
+    );
+}
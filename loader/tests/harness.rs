@@ -0,0 +1,230 @@
+use std::{net::TcpListener, path::PathBuf, sync::Mutex, time::Duration};
+
+use loader::{
+    avatar::{self, Avatar, Parameter, ParameterType},
+    metrics,
+    parameters,
+    session::{self, Direction},
+    tcp::{Framing, OscStream},
+    testing::{self, Harness},
+    Config,
+};
+use rosc::{OscMessage, OscPacket, OscType};
+use serde_json::json;
+
+/// The clock sends every second by default
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The loader's avatar and parameter state is global
+static SERIAL: Mutex<()> = Mutex::new(());
+
+/// A clean checkout hasn't built the plugins yet, their tests are skipped until `cargo build --workspace`
+///
+/// CI builds them first, so a missing plugin fails there instead of passing silently
+fn plugin(name: &str) -> Option<PathBuf> {
+    match testing::plugin_path(name) {
+        Ok(path) => Some(path),
+        Err(error) if std::env::var_os("CI").is_some() => panic!("{error}"),
+        Err(error) => {
+            eprintln!("Skipping, {error}");
+            None
+        }
+    }
+}
+
+fn clock(avatar: Avatar) -> Option<Harness> {
+    avatar::set_current(avatar);
+
+    let clock = plugin("clock")?;
+    Some(Harness::start(&[clock]).unwrap())
+}
+
+#[test]
+fn clock_sends_every_hand_in_one_bundle() {
+    let _serial = SERIAL
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner);
+    let Some(harness) = clock(Avatar::default()) else {
+        return;
+    };
+
+    let packet = harness.recv(TIMEOUT).unwrap();
+    let OscPacket::Bundle(bundle) = packet else {
+        panic!("Expected a bundle, got {packet:?}");
+    };
+
+    let mut addrs = Vec::new();
+    for packet in bundle.content {
+        let OscPacket::Message(message) = packet else {
+            panic!("Expected a message, got {packet:?}");
+        };
+
+        let Some(OscType::Float(hand)) = message.args.first() else {
+            panic!("Expected a float, got {:?}", message.args);
+        };
+
+        assert!((0.0..=1.0).contains(hand), "{} is {hand}", message.addr);
+        addrs.push(message.addr);
+    }
+
+    addrs.sort();
+    assert_eq!(
+        addrs,
+        [
+            "/avatar/parameters/VRCOSC/Clock/Hours",
+            "/avatar/parameters/VRCOSC/Clock/Minutes",
+            "/avatar/parameters/VRCOSC/Clock/Seconds",
+        ]
+    );
+
+    // The router remembers what it sent
+    assert!(parameters::get("VRCOSC/Clock/Hours").is_some());
+}
+
+#[test]
+fn clock_skips_hands_the_avatar_doesnt_have() {
+    let _serial = SERIAL
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner);
+    let Some(harness) = clock(Avatar {
+        id:         Some("avtr_clock".into()),
+        parameters: vec![Parameter {
+            name: "VRCOSC/Clock/Seconds".into(),
+            kind: ParameterType::Float,
+        }],
+    }) else {
+        return;
+    };
+
+    let messages = harness.messages(Duration::from_millis(2500)).unwrap();
+
+    assert!(!messages.is_empty());
+    assert!(messages
+        .iter()
+        .all(|message| message.addr == "/avatar/parameters/VRCOSC/Clock/Seconds"));
+}
+
+#[test]
+fn clock_reloads_with_its_saved_config() {
+    let _serial = SERIAL
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner);
+
+    avatar::set_current(Avatar::default());
+    let Some(clock) = plugin("clock") else {
+        return;
+    };
+    let harness = Harness::start(std::slice::from_ref(&clock)).unwrap();
+
+    // The clock saves its config when it starts, which would overwrite the one saved here
+    harness.recv(TIMEOUT).unwrap();
+
+    // Smooth seconds change a little on every poll, instead of once a second
+    let seconds = |config| {
+        harness.save_config(&clock, &config).unwrap();
+        // Skip what the previous instance sent before it stopped
+        harness.messages(Duration::from_millis(250)).unwrap();
+        harness
+            .messages(Duration::from_millis(1500))
+            .unwrap()
+            .iter()
+            .filter(|message| message.addr == "/avatar/parameters/VRCOSC/Clock/Seconds")
+            .count()
+    };
+
+    let smooth = seconds(json!({ "mode": false, "polling": 50, "smooth": true }));
+    let every_second = seconds(json!({ "mode": false, "polling": 1000, "smooth": false }));
+
+    assert!(smooth > 5, "Sent {smooth} seconds with the smooth config");
+    assert!(
+        every_second <= 3,
+        "Sent {every_second} seconds with the default config"
+    );
+}
+
+#[test]
+fn records_what_plugins_send() {
+    let _serial = SERIAL
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner);
+    let path = std::env::temp_dir().join(format!("vrc-osc-session-{}.jsonl", std::process::id()));
+    let config = Config {
+        record: Some(path.to_string_lossy().into_owned()),
+        ..Config::default()
+    };
+
+    avatar::set_current(Avatar::default());
+    let Some(clock) = plugin("clock") else {
+        return;
+    };
+    let harness = Harness::with_config(config, &[clock]).unwrap();
+    harness
+        .expect_message("/avatar/parameters/VRCOSC/Clock/*", TIMEOUT)
+        .unwrap();
+
+    let records = session::load(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+
+    assert!(records.iter().any(|record| {
+        record.direction == Direction::Outgoing && record.source.as_deref() == Some("Clock")
+    }));
+}
+
+#[test]
+fn serves_what_plugins_send_as_metrics() {
+    let _serial = SERIAL
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner);
+    let Some(harness) = clock(Avatar::default()) else {
+        return;
+    };
+    harness.recv(TIMEOUT).unwrap();
+
+    let addr = metrics::serve("127.0.0.1:0").unwrap();
+    let text = ureq::get(&format!("http://{addr}/metrics"))
+        .call()
+        .unwrap()
+        .into_string()
+        .unwrap();
+
+    for line in [
+        r#"vrc_osc_plugin_packets_total{plugin="Clock",direction="outgoing"}"#,
+        r#"vrc_osc_address_packets_total{prefix="/avatar/parameters",direction="outgoing"}"#,
+    ] {
+        assert!(text.contains(line), "{line} is missing from\n{text}");
+    }
+}
+
+#[test]
+fn tcp_peers_receive_what_vrchat_sends() {
+    let _serial = SERIAL
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner);
+    let tcp_addr = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let config = Config {
+        tcp_addr: Some(tcp_addr.to_string()),
+        ..Config::default()
+    };
+
+    avatar::set_current(Avatar::default());
+    let harness = Harness::with_config(config, &[]).unwrap();
+    let mut peer = OscStream::connect(tcp_addr, Framing::Slip).unwrap();
+    std::thread::sleep(Duration::from_millis(100)); // Accepted on another thread
+
+    let message = OscPacket::Message(OscMessage {
+        addr: "/avatar/parameters/VRCOSC/Media/Play".into(),
+        args: vec![OscType::Bool(true)],
+    });
+    harness.send(&message).unwrap();
+    assert_eq!(peer.recv().unwrap(), Some(message));
+
+    let chatbox = OscPacket::Message(OscMessage {
+        addr: "/chatbox/input".into(),
+        args: vec![OscType::String("Hello".into()), OscType::Bool(true)],
+    });
+    peer.send(&chatbox).unwrap();
+    harness.expect_message("/chatbox/input", TIMEOUT).unwrap();
+}
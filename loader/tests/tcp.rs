@@ -0,0 +1,124 @@
+use std::{io::Cursor, net::TcpListener, thread::JoinHandle};
+
+use loader::tcp::{Framing, OscStream, MAX_FRAME};
+use rosc::{OscBundle, OscMessage, OscPacket, OscType};
+
+/// A local peer that echoes every packet back until the connection closes
+fn echo_peer(framing: Framing) -> (OscStream, JoinHandle<()>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let peer = std::thread::spawn(move || {
+        let (stream, _addr) = listener.accept().unwrap();
+        let mut peer = OscStream::new(stream, framing).unwrap();
+        while let Some(packet) = peer.recv().unwrap() {
+            peer.send(&packet).unwrap();
+        }
+    });
+
+    (OscStream::connect(addr, framing).unwrap(), peer)
+}
+
+fn message(addr: &str, arg: impl Into<OscType>) -> OscPacket {
+    OscPacket::Message(OscMessage {
+        addr: addr.into(),
+        args: vec![arg.into()],
+    })
+}
+
+fn round_trip(framing: Framing, packets: &[OscPacket]) {
+    let (mut stream, peer) = echo_peer(framing);
+    for packet in packets {
+        stream.send(packet).unwrap();
+    }
+
+    for packet in packets {
+        assert_eq!(stream.recv().unwrap().as_ref(), Some(packet));
+    }
+
+    drop(stream);
+    peer.join().unwrap();
+}
+
+fn packets() -> Vec<OscPacket> {
+    let clock = OscPacket::Bundle(OscBundle {
+        timetag: (0, 1).into(),
+        content: vec![
+            message("/avatar/parameters/VRCOSC/Clock/Hours", 0.25),
+            message("/avatar/parameters/VRCOSC/Clock/Minutes", 0.5),
+            message("/avatar/parameters/VRCOSC/Clock/Seconds", 0.75),
+        ],
+    });
+
+    vec![
+        message("/chatbox/input", "Hello"),
+        message("/avatar/parameters/VRCOSC/Media/Play", true),
+        clock,
+        // SLIP's END and ESC bytes
+        message("/blob", OscType::Blob(vec![0xC0, 0xDB, 0xDC, 0xDD, 0xC0])),
+    ]
+}
+
+#[test]
+fn length_prefix_round_trip() {
+    round_trip(Framing::LengthPrefix, &packets());
+}
+
+#[test]
+fn slip_round_trip() {
+    round_trip(Framing::Slip, &packets());
+}
+
+#[test]
+fn bundles_bigger_than_a_datagram_round_trip() {
+    let content = (0..2048)
+        .map(|index| message(&format!("/avatar/parameters/Big/{index}"), index))
+        .collect();
+
+    let bundle = OscPacket::Bundle(OscBundle {
+        timetag: (0, 1).into(),
+        content,
+    });
+
+    round_trip(Framing::LengthPrefix, std::slice::from_ref(&bundle));
+    round_trip(Framing::Slip, &[bundle]);
+}
+
+#[test]
+fn length_prefix_frames() {
+    let frame = Framing::LengthPrefix.encode(b"/a\0\0");
+
+    assert_eq!(frame, b"\0\0\0\x04/a\0\0");
+}
+
+#[test]
+fn slip_frames_escape_special_bytes() {
+    let frame = Framing::Slip.encode(&[0x01, 0xC0, 0xDB, 0x02]);
+
+    assert_eq!(frame, [0xC0, 0x01, 0xDB, 0xDC, 0xDB, 0xDD, 0x02, 0xC0]);
+}
+
+#[test]
+fn slip_skips_empty_frames() {
+    let mut reader = Cursor::new([0xC0, 0xC0, 0x01, 0xC0, 0xC0, 0x02, 0xC0]);
+
+    assert_eq!(Framing::Slip.read(&mut reader).unwrap(), Some(vec![0x01]));
+    assert_eq!(Framing::Slip.read(&mut reader).unwrap(), Some(vec![0x02]));
+    assert_eq!(Framing::Slip.read(&mut reader).unwrap(), None);
+}
+
+#[test]
+fn truncated_frames_are_errors() {
+    let mut slip = Cursor::new([0xC0, 0x01, 0x02]);
+    let mut length_prefix = Cursor::new([0, 0, 0, 8, 0x01]);
+
+    assert!(Framing::Slip.read(&mut slip).is_err());
+    assert!(Framing::LengthPrefix.read(&mut length_prefix).is_err());
+}
+
+#[test]
+fn oversized_frames_are_rejected() {
+    let size = u32::try_from(MAX_FRAME + 1).unwrap();
+    let mut reader = Cursor::new(size.to_be_bytes());
+
+    assert!(Framing::LengthPrefix.read(&mut reader).is_err());
+}
@@ -0,0 +1,462 @@
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use rosc::{OscMessage, OscPacket, OscType};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    avatar::{self, Avatar, ParameterType, PARAMETERS_PREFIX},
+    metrics,
+    supervisor::RunningPlugin,
+};
+
+/// What to do with outgoing parameters that don't match the current avatar
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ValidationMode {
+    #[default]
+    Off,
+    Log,
+    Drop,
+    /// Converts between bools, ints and floats, parameters the avatar doesn't have are dropped
+    Coerce,
+}
+
+/// Mismatches sent by a plugin since it was loaded, shown in its status and exported as metrics
+#[derive(Debug, Default, Serialize)]
+pub struct ValidationCounters {
+    pub missing:    AtomicU64,
+    pub wrong_type: AtomicU64,
+    pub dropped:    AtomicU64,
+    pub coerced:    AtomicU64,
+}
+
+/// Counts for the plugin and in the metrics
+fn count(counter: &AtomicU64, plugin: &str, result: &'static str) {
+    counter.fetch_add(1, Ordering::Relaxed);
+    metrics::validation(plugin, result);
+}
+
+enum Mismatch {
+    Missing,
+    WrongType {
+        expected: ParameterType,
+        actual:   OscType,
+    },
+}
+
+/// Checks outgoing parameters against the current avatar's schema
+pub struct Validator {
+    mode:     ValidationMode,
+    /// Each mismatch is only logged once per plugin and address
+    reported: Mutex<HashSet<(String, String)>>,
+}
+
+impl Validator {
+    #[must_use]
+    pub fn new(mode: ValidationMode) -> Self {
+        Self {
+            mode,
+            reported: Mutex::default(),
+        }
+    }
+
+    #[must_use]
+    pub fn is_enabled(&self) -> bool {
+        self.mode != ValidationMode::Off
+    }
+
+    /// Mismatches are reported again for the new avatar
+    ///
+    /// # Panics
+    ///
+    /// Will panic if the reported mismatches lock was poisoned
+    pub fn avatar_changed(&self) {
+        self.reported
+            .lock()
+            .expect("Failed to lock the reported mismatches")
+            .clear();
+    }
+
+    /// Validates every parameter in the packet, returns `false` if nothing is left to send
+    ///
+    /// Nothing is checked until the current avatar is known
+    pub fn validate(&self, packet: &mut OscPacket, plugin: &RunningPlugin) -> bool {
+        if !self.is_enabled() {
+            return true;
+        }
+
+        let name = &plugin.plugin.metadata.name;
+        avatar::with_current(|avatar| self.check(packet, name, &plugin.validation, avatar))
+    }
+
+    fn check(
+        &self,
+        packet: &mut OscPacket,
+        plugin: &str,
+        counters: &ValidationCounters,
+        avatar: &Avatar,
+    ) -> bool {
+        !self.is_enabled()
+            || !avatar.is_known()
+            || self.validate_packet(packet, plugin, counters, avatar)
+    }
+
+    fn validate_packet(
+        &self,
+        packet: &mut OscPacket,
+        plugin: &str,
+        counters: &ValidationCounters,
+        avatar: &Avatar,
+    ) -> bool {
+        match packet {
+            OscPacket::Message(message) => self.validate_message(message, plugin, counters, avatar),
+            OscPacket::Bundle(bundle) => {
+                bundle
+                    .content
+                    .retain_mut(|packet| self.validate_packet(packet, plugin, counters, avatar));
+
+                !bundle.content.is_empty()
+            }
+        }
+    }
+
+    fn validate_message(
+        &self,
+        message: &mut OscMessage,
+        plugin: &str,
+        counters: &ValidationCounters,
+        avatar: &Avatar,
+    ) -> bool {
+        let Some(name) = message.addr.strip_prefix(PARAMETERS_PREFIX) else {
+            return true; // Not a parameter
+        };
+
+        let Some(arg) = message.args.first() else {
+            return true; // VRChat ignores parameters without a value anyway
+        };
+
+        let mismatch = match avatar.parameter(name) {
+            None => Mismatch::Missing,
+            Some(parameter) if parameter_type(arg) != Some(parameter.kind) => Mismatch::WrongType {
+                expected: parameter.kind,
+                actual:   arg.clone(),
+            },
+            Some(_) => return true,
+        };
+
+        let addr = &message.addr;
+        let description = match &mismatch {
+            Mismatch::Missing => {
+                count(&counters.missing, plugin, "missing");
+                format!("{plugin} sent {addr} which the current avatar doesn't have")
+            }
+            Mismatch::WrongType { expected, actual } => {
+                count(&counters.wrong_type, plugin, "wrong-type");
+                format!(
+                    "{plugin} sent {addr} as {actual:?} but the avatar declares it as {expected:?}"
+                )
+            }
+        };
+
+        let first = self
+            .reported
+            .lock()
+            .expect("Failed to lock the reported mismatches")
+            .insert((plugin.to_owned(), addr.clone()));
+
+        if first {
+            tracing::warn!("{description}");
+        }
+
+        let coerced = match (self.mode, mismatch) {
+            (ValidationMode::Off | ValidationMode::Log, _) => return true,
+            (ValidationMode::Coerce, Mismatch::WrongType { expected, actual }) => {
+                coerce(&actual, expected)
+            }
+            (ValidationMode::Drop | ValidationMode::Coerce, _) => None,
+        };
+
+        if let Some(arg) = coerced {
+            count(&counters.coerced, plugin, "coerced");
+            message.args[0] = arg;
+            true
+        } else {
+            count(&counters.dropped, plugin, "dropped");
+            false
+        }
+    }
+}
+
+const fn parameter_type(arg: &OscType) -> Option<ParameterType> {
+    match arg {
+        OscType::Bool(_) => Some(ParameterType::Bool),
+        OscType::Int(_) => Some(ParameterType::Int),
+        OscType::Float(_) => Some(ParameterType::Float),
+        _ => None,
+    }
+}
+
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::cast_precision_loss)]
+fn coerce(arg: &OscType, kind: ParameterType) -> Option<OscType> {
+    Some(match (arg, kind) {
+        (OscType::Int(int), ParameterType::Bool) => OscType::Bool(*int != 0),
+        (OscType::Float(float), ParameterType::Bool) => OscType::Bool(*float != 0.0),
+        (OscType::Bool(bool), ParameterType::Int) => OscType::Int(i32::from(*bool)),
+        (OscType::Float(float), ParameterType::Int) => OscType::Int(float.round() as i32),
+        (OscType::Bool(bool), ParameterType::Float) => OscType::Float(f32::from(u8::from(*bool))),
+        (OscType::Int(int), ParameterType::Float) => OscType::Float(*int as f32),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use rosc::OscBundle;
+
+    use super::*;
+    use crate::avatar::Parameter;
+
+    fn avatar() -> Avatar {
+        Avatar {
+            id:         Some("avtr_test".into()),
+            parameters: vec![
+                Parameter {
+                    name: "Muted".into(),
+                    kind: ParameterType::Bool,
+                },
+                Parameter {
+                    name: "Hours".into(),
+                    kind: ParameterType::Float,
+                },
+            ],
+        }
+    }
+
+    fn parameter(name: &str, arg: OscType) -> OscPacket {
+        OscPacket::Message(OscMessage {
+            addr: format!("{PARAMETERS_PREFIX}{name}"),
+            args: vec![arg],
+        })
+    }
+
+    /// Validates the packet from a `Test` plugin, returns whether it's sent and what's left of it
+    fn check(
+        validator: &Validator,
+        counters: &ValidationCounters,
+        avatar: &Avatar,
+        mut packet: OscPacket,
+    ) -> (bool, OscPacket) {
+        let sent = validator.check(&mut packet, "Test", counters, avatar);
+        (sent, packet)
+    }
+
+    fn counts(counters: &ValidationCounters) -> [u64; 4] {
+        [
+            &counters.missing,
+            &counters.wrong_type,
+            &counters.dropped,
+            &counters.coerced,
+        ]
+        .map(|counter| counter.load(Ordering::Relaxed))
+    }
+
+    #[test]
+    fn matching_parameters_and_other_addresses_are_sent() {
+        let validator = Validator::new(ValidationMode::Drop);
+        let counters = ValidationCounters::default();
+        for packet in [
+            parameter("Muted", OscType::Bool(true)),
+            parameter("Hours", OscType::Float(1.0)),
+            OscPacket::Message(OscMessage {
+                addr: "/chatbox/typing".into(),
+                args: vec![OscType::Bool(true)],
+            }),
+        ] {
+            assert_eq!(
+                check(&validator, &counters, &avatar(), packet.clone()),
+                (true, packet)
+            );
+        }
+
+        assert_eq!(counts(&counters), [0; 4]);
+    }
+
+    #[test]
+    fn off_sends_everything_without_counting() {
+        let validator = Validator::new(ValidationMode::Off);
+        let counters = ValidationCounters::default();
+        let packet = parameter("Missing", OscType::Int(1));
+
+        assert_eq!(
+            check(&validator, &counters, &avatar(), packet.clone()),
+            (true, packet)
+        );
+        assert_eq!(counts(&counters), [0; 4]);
+    }
+
+    #[test]
+    fn log_counts_mismatches_but_sends_them() {
+        let validator = Validator::new(ValidationMode::Log);
+        let counters = ValidationCounters::default();
+        let missing = parameter("Missing", OscType::Int(1));
+        let wrong_type = parameter("Muted", OscType::Int(1));
+
+        assert_eq!(
+            check(&validator, &counters, &avatar(), missing.clone()),
+            (true, missing)
+        );
+        assert_eq!(
+            check(&validator, &counters, &avatar(), wrong_type.clone()),
+            (true, wrong_type)
+        );
+        assert_eq!(counts(&counters), [1, 1, 0, 0]);
+    }
+
+    #[test]
+    fn drop_drops_missing_parameters_and_wrong_types() {
+        let validator = Validator::new(ValidationMode::Drop);
+        let counters = ValidationCounters::default();
+
+        assert!(
+            !check(
+                &validator,
+                &counters,
+                &avatar(),
+                parameter("Missing", OscType::Int(1))
+            )
+            .0
+        );
+        assert!(
+            !check(
+                &validator,
+                &counters,
+                &avatar(),
+                parameter("Muted", OscType::Int(1))
+            )
+            .0
+        );
+        assert_eq!(counts(&counters), [1, 1, 2, 0]);
+    }
+
+    #[test]
+    fn coerce_converts_wrong_types_and_drops_missing_parameters() {
+        let validator = Validator::new(ValidationMode::Coerce);
+        let counters = ValidationCounters::default();
+
+        assert_eq!(
+            check(
+                &validator,
+                &counters,
+                &avatar(),
+                parameter("Muted", OscType::Int(2))
+            ),
+            (true, parameter("Muted", OscType::Bool(true)))
+        );
+        assert_eq!(
+            check(
+                &validator,
+                &counters,
+                &avatar(),
+                parameter("Hours", OscType::Int(3))
+            ),
+            (true, parameter("Hours", OscType::Float(3.0)))
+        );
+        assert!(
+            !check(
+                &validator,
+                &counters,
+                &avatar(),
+                parameter("Muted", OscType::String("on".into()))
+            )
+            .0
+        );
+        assert!(
+            !check(
+                &validator,
+                &counters,
+                &avatar(),
+                parameter("Missing", OscType::Int(1))
+            )
+            .0
+        );
+        assert_eq!(counts(&counters), [1, 3, 2, 2]);
+    }
+
+    #[test]
+    fn bundles_keep_only_what_validates() {
+        let validator = Validator::new(ValidationMode::Drop);
+        let counters = ValidationCounters::default();
+        let bundle = |content| {
+            OscPacket::Bundle(OscBundle {
+                timetag: (0, 1).into(),
+                content,
+            })
+        };
+
+        let mixed = bundle(vec![
+            parameter("Muted", OscType::Bool(true)),
+            parameter("Missing", OscType::Int(1)),
+        ]);
+        assert_eq!(
+            check(&validator, &counters, &avatar(), mixed),
+            (true, bundle(vec![parameter("Muted", OscType::Bool(true))]))
+        );
+
+        let invalid = bundle(vec![parameter("Missing", OscType::Int(1))]);
+        assert!(!check(&validator, &counters, &avatar(), invalid).0);
+    }
+
+    #[test]
+    fn nothing_is_validated_while_the_avatar_is_unknown() {
+        let validator = Validator::new(ValidationMode::Drop);
+        let counters = ValidationCounters::default();
+        let packet = parameter("Missing", OscType::Int(1));
+
+        // What the router sees after an avatar change until the new avatar is found
+        assert_eq!(
+            check(&validator, &counters, &Avatar::default(), packet.clone()),
+            (true, packet)
+        );
+        assert_eq!(counts(&counters), [0; 4]);
+    }
+
+    #[test]
+    fn mismatches_are_reported_again_after_an_avatar_change() {
+        let validator = Validator::new(ValidationMode::Log);
+        let counters = ValidationCounters::default();
+        let reported = || validator.reported.lock().unwrap().len();
+
+        check(
+            &validator,
+            &counters,
+            &avatar(),
+            parameter("Missing", OscType::Int(1)),
+        );
+        check(
+            &validator,
+            &counters,
+            &avatar(),
+            parameter("Missing", OscType::Int(2)),
+        );
+        assert_eq!(reported(), 1);
+
+        validator.avatar_changed();
+        assert_eq!(reported(), 0);
+
+        check(
+            &validator,
+            &counters,
+            &avatar(),
+            parameter("Missing", OscType::Int(3)),
+        );
+        assert_eq!(reported(), 1);
+        assert_eq!(counts(&counters), [3, 0, 0, 0]);
+    }
+}
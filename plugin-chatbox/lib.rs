@@ -5,7 +5,7 @@ use std::{net::UdpSocket, time::Duration};
 
 use anyhow::Result;
 use derive_config::DeriveTomlConfig;
-use loader::{ChatMessage, Config as LoaderConfig};
+use loader::ChatMessage;
 use rosc::{OscMessage, OscPacket, OscType};
 use serde::{Deserialize, Serialize};
 
@@ -29,26 +29,34 @@ impl Default for Config {
     }
 }
 
-#[no_mangle]
+loader::export_plugin! {
+    name: "Chatbox",
+    capabilities: Capabilities::OSC_PRODUCER,
+    config: Config,
+    load: load,
+}
+
 #[allow(clippy::needless_pass_by_value)]
 #[tokio::main(flavor = "current_thread")]
-async extern "Rust" fn load(socket: UdpSocket) -> Result<()> {
+async fn load(socket: UdpSocket) -> Result<()> {
     let config = Config::load().unwrap_or_default();
-    let plugin_names = loader::get_plugin_names()?;
-    let loader_config = LoaderConfig::load()?;
 
     config.save()?;
+    let config = loader::env::apply(config, &loader::env::plugin_prefix("Chatbox"))?;
 
     let mut previous_message: (String, String) = config.message.clone();
     loop {
         tokio::time::sleep(Duration::from_millis(config.polling)).await;
+        if loader::plugin::unloading() {
+            return Ok(());
+        }
 
-        let message = loader::chat_message(&config.message, &plugin_names, &loader_config).await?;
+        let message = loader::host::chat(&config.message).await;
         if message == previous_message && config.send_once {
             continue;
         }
 
-        println!("{}", message.1);
+        tracing::info!("{}", message.1);
         previous_message = message.clone();
 
         let packet = OscPacket::Message(OscMessage {
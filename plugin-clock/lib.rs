@@ -9,7 +9,7 @@ use std::{
 
 use anyhow::Result;
 use derive_config::DeriveTomlConfig;
-use rosc::{OscMessage, OscPacket, OscType};
+use loader::sender::ParameterSender;
 use serde::{Deserialize, Serialize};
 
 #[derive(Clone, Debug, DeriveTomlConfig, Deserialize, Serialize)]
@@ -29,13 +29,26 @@ impl Default for Config {
     }
 }
 
-#[no_mangle]
+loader::export_plugin! {
+    name: "Clock",
+    capabilities: Capabilities::OSC_PRODUCER,
+    config: Config,
+    load: load,
+}
+
 #[allow(clippy::needless_pass_by_value)]
 #[tokio::main(flavor = "current_thread")]
-async extern "Rust" fn load(socket: UdpSocket) -> Result<()> {
-    let config = Config::load()?;
+async fn load(socket: UdpSocket) -> Result<()> {
+    let config = Config::load().unwrap_or_default();
+    config.save()?;
+    let config = loader::env::apply(config, &loader::env::plugin_prefix("Clock"))?;
+
+    // VRChat syncs floats with 8 bits, resend now and then in case a packet was lost
+    let mut sender = ParameterSender::new(&socket)
+        .with_epsilon(1.0 / 512.0)
+        .with_refresh(Duration::from_secs(10));
 
-    loop {
+    while !loader::plugin::unloading() {
         let duration = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?;
         let seconds = duration.as_secs();
 
@@ -57,16 +70,18 @@ async extern "Rust" fn load(socket: UdpSocket) -> Result<()> {
             ("Seconds", seconds / 60.0),
         ]);
 
-        for (parameter, arg) in parameters {
-            let packet = OscPacket::Message(OscMessage {
-                addr: "/avatar/parameters/VRCOSC/Clock/".to_owned() + parameter,
-                args: vec![OscType::Float(arg as f32)],
-            });
+        let avatar = loader::host::avatar();
+        let hands = parameters
+            .into_iter()
+            .map(|(parameter, arg)| ("VRCOSC/Clock/".to_owned() + parameter, arg as f32))
+            // The current avatar may not have every hand
+            .filter(|(name, _)| !avatar.is_known() || avatar.parameter(name).is_some());
 
-            let msg_buf = rosc::encoder::encode(&packet)?;
-            socket.send(&msg_buf)?;
-        }
+        // All the hands move at once
+        sender.set_all(hands)?;
 
         std::thread::sleep(Duration::from_millis(config.polling));
     }
+
+    Ok(())
 }
@@ -1,5 +1,19 @@
+#[cfg(unix)]
+use crate::unix::load;
+#[cfg(windows)]
+use crate::windows::load;
+
 #[cfg(unix)]
 pub mod unix;
 
 #[cfg(windows)]
 pub mod windows;
+
+// macOS doesn't support media keys
+loader::export_plugin! {
+    name: "Control",
+    platforms: Platforms::WINDOWS.union(Platforms::LINUX),
+    capabilities: Capabilities::OSC_CONSUMER.union(Capabilities::OSC_PRODUCER),
+    subscriptions: ["/avatar/parameters/VRCOSC/Media/*"],
+    load: load,
+}
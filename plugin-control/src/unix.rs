@@ -2,23 +2,36 @@ use std::net::UdpSocket;
 
 use anyhow::Result;
 use enigo::{Enigo, Key, KeyboardControllable};
+use loader::pattern::Pattern;
 use rosc::{decoder::MTU, OscPacket};
 
-#[no_mangle]
+/// # Errors
+///
+/// # Panics
+#[allow(clippy::needless_pass_by_value)]
 #[tokio::main(flavor = "current_thread")]
 pub async fn load(socket: UdpSocket) -> Result<()> {
     let mut enigo = Enigo::new();
+    let media = Pattern::new("/avatar/parameters/VRCOSC/Media/*")?;
 
     let mut buf = [0u8; MTU];
     loop {
         let size = socket.recv(&mut buf)?;
+        if loader::plugin::unloading() {
+            return Ok(());
+        }
+
         let (_buf, packet) = rosc::decoder::decode_udp(&buf[..size])?;
         let OscPacket::Message(packet) = packet else {
-            continue; // I don't think VRChat uses bundles
+            continue; // The loader unpacks bundles
+        };
+
+        let Some(captures) = media.captures(&packet.addr) else {
+            continue; // Not a media parameter
         };
 
-        let addr = packet.addr.replace("/avatar/parameters/VRCOSC/Media/", "");
-        match addr.as_ref() {
+        let addr = captures[0];
+        match addr {
             "Play" => enigo.key_click(Key::MediaPlayPause),
             "Next" => enigo.key_click(Key::MediaNextTrack),
             "Previous" => enigo.key_click(Key::MediaPrevTrack),
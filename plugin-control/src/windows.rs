@@ -1,7 +1,8 @@
-use std::{collections::HashMap, net::UdpSocket};
+use std::net::UdpSocket;
 
 use anyhow::Result;
-use rosc::{decoder::MTU, OscMessage, OscPacket, OscType};
+use loader::{pattern::Pattern, sender::ParameterSender};
+use rosc::{decoder::MTU, OscPacket, OscType};
 use windows::Media::{
     Control::GlobalSystemMediaTransportControlsSessionManager as GSMTCSM,
     MediaPlaybackAutoRepeatMode,
@@ -10,22 +11,30 @@ use windows::Media::{
 /// # Errors
 ///
 /// # Panics
-#[no_mangle]
 #[allow(clippy::needless_pass_by_value)]
 #[tokio::main(flavor = "current_thread")]
-pub async extern "Rust" fn load(socket: UdpSocket) -> Result<()> {
+pub async fn load(socket: UdpSocket) -> Result<()> {
     let manager = GSMTCSM::RequestAsync()?.await?;
-    let mut previous_parameters = HashMap::new();
+    let media = Pattern::new("/avatar/parameters/VRCOSC/Media/*")?;
+    let mut sender = ParameterSender::new(&socket);
     let mut buf = [0u8; MTU];
 
     loop {
         let size = socket.recv(&mut buf)?;
+        if loader::plugin::unloading() {
+            return Ok(());
+        }
+
         let (_buf, packet) = rosc::decoder::decode_udp(&buf[..size])?;
         let OscPacket::Message(packet) = packet else {
-            continue; // I don't think VRChat uses bundles
+            continue; // The loader unpacks bundles
+        };
+
+        let Some(captures) = media.captures(&packet.addr) else {
+            continue; // Not a media parameter
         };
 
-        let addr = packet.addr.replace("/avatar/parameters/VRCOSC/Media/", "");
+        let addr = captures[0];
         let Some(arg) = packet.args.first() else {
             continue; // No first argument was supplied
         };
@@ -34,7 +43,7 @@ pub async extern "Rust" fn load(socket: UdpSocket) -> Result<()> {
             continue; // No media is currently playing
         };
 
-        match addr.as_ref() {
+        match addr {
             "Play" => {
                 let OscType::Bool(play) = arg.to_owned() else {
                     continue;
@@ -84,46 +93,26 @@ pub async extern "Rust" fn load(socket: UdpSocket) -> Result<()> {
             }
             _ => {
                 let playback_info = session.GetPlaybackInfo()?;
-                let mut parameters = HashMap::new();
 
                 if let Ok(playback_status) = playback_info.PlaybackStatus() {
                     let play = OscType::Bool(playback_status.0 == 4);
-                    if previous_parameters.get("Play") != Some(&play) {
-                        parameters.insert("Play", play.clone());
-                        previous_parameters.insert("Play", play);
-                    }
+                    sender.set("VRCOSC/Media/Play", play)?;
                 }
 
                 if let Ok(shuffle_ref) = playback_info.IsShuffleActive() {
                     if let Ok(shuffle) = shuffle_ref.Value() {
                         let shuffle = OscType::Bool(shuffle);
-                        if previous_parameters.get("Shuffle") != Some(&shuffle) {
-                            parameters.insert("Shuffle", shuffle.clone());
-                            previous_parameters.insert("Shuffle", shuffle);
-                        }
+                        sender.set("VRCOSC/Media/Shuffle", shuffle)?;
                     }
                 }
 
                 if let Ok(repeat_mode_ref) = playback_info.AutoRepeatMode() {
                     if let Ok(repeat_mode) = repeat_mode_ref.Value() {
                         let repeat = OscType::Int(repeat_mode.0);
-                        if previous_parameters.get("Repeat") != Some(&repeat) {
-                            parameters.insert("Repeat", repeat.clone());
-                            previous_parameters.insert("Repeat", repeat);
-                        }
+                        sender.set("VRCOSC/Media/Repeat", repeat)?;
                     }
                 }
 
-                for (param, arg) in parameters {
-                    let packet = OscPacket::Message(OscMessage {
-                        addr: format!("/avatar/parameters/VRCOSC/Media/{param}"),
-                        args: vec![arg],
-                    });
-
-                    let msg_buf = rosc::encoder::encode(&packet)?;
-                    socket.send(&msg_buf)?;
-                }
-
                 continue;
             }
         }?
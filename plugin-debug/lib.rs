@@ -3,20 +3,30 @@ use std::net::UdpSocket;
 use anyhow::Result;
 use rosc::OscPacket;
 
-#[no_mangle]
+loader::export_plugin! {
+    name: "Debug",
+    capabilities: Capabilities::OSC_CONSUMER,
+    subscriptions: ["//*"], // Everything
+    load: load,
+}
+
 #[allow(clippy::needless_pass_by_value)]
 #[tokio::main(flavor = "current_thread")]
-async extern "Rust" fn load(socket: UdpSocket) -> Result<()> {
-    println!("Debug Enabled");
+async fn load(socket: UdpSocket) -> Result<()> {
+    tracing::info!("Debug Enabled, packets are logged at the debug level");
 
     let mut buf = [0u8; rosc::decoder::MTU];
     loop {
         let size = socket.recv(&mut buf).unwrap();
+        if loader::plugin::unloading() {
+            return Ok(());
+        }
+
         let (_buf, packet) = rosc::decoder::decode_udp(&buf[..size]).unwrap();
         let OscPacket::Message(packet) = packet else {
-            continue; // I don't think VRChat uses bundles
+            continue; // The loader unpacks bundles
         };
 
-        println!("{} | {:?}", packet.addr, packet.args);
+        tracing::debug!("{} | {:?}", packet.addr, packet.args);
     }
 }
@@ -5,15 +5,14 @@ mod model;
 use std::{net::UdpSocket, sync::OnceLock};
 
 use anyhow::{Context, Error, Result};
-use async_ffi::async_ffi;
 use derive_config::DeriveTomlConfig;
 #[cfg(debug_assertions)]
 use dotenvy_macro::dotenv;
 use inquire::Text;
+use loader::ChatMessage;
 use model::Track;
 use serde::{Deserialize, Serialize};
 use terminal_link::Link;
-use tokio::runtime::Handle;
 
 use crate::model::LastFM;
 
@@ -47,37 +46,57 @@ impl Default for Config {
 fn config() -> Result<&'static Config> {
     static CONFIG: OnceLock<Config> = OnceLock::new();
     CONFIG.get_or_try_init(|| {
-        Config::load().or_else(|_| {
+        let config = Config::load().or_else(|_| {
+            if !loader::host::interactive() {
+                return Ok(Config::default()); // Left to the environment, nothing is saved
+            }
+
             println!("The LastFM plugin requires you to setup a scrobbler app or service");
             println!("https://www.last.fm/about/trackmymusic");
 
-            Ok::<Config, Error>(Config {
+            let config = Config {
                 username: Text::new("LastFM Username: ").prompt()?,
                 ..Default::default()
-            })
-        })
+            };
+
+            config.save()?;
+
+            Ok::<Config, Error>(config)
+        })?;
+
+        let prefix = loader::env::plugin_prefix("LastFM");
+        let config = loader::env::apply(config, &prefix)?;
+        let missing = [
+            ("api_key", config.api_key.is_empty()),
+            ("username", config.username.is_empty()),
+        ]
+        .into_iter()
+        .filter_map(|(key, missing)| missing.then_some(key))
+        .collect::<Vec<_>>();
+
+        loader::env::require("The LastFM plugin", &prefix, &missing)?;
+
+        Ok(config)
     })
 }
 
-#[no_mangle]
+loader::export_plugin! {
+    name: "LastFM",
+    capabilities: Capabilities::CHAT,
+    config: Config,
+    load: load,
+    chat: chat,
+}
+
 #[allow(clippy::needless_pass_by_value)]
 #[tokio::main(flavor = "current_thread")]
-async extern "Rust" fn load(_: UdpSocket) -> Result<()> {
-    config()?.save()?;
+async fn load(_: UdpSocket) -> Result<()> {
+    config()?;
 
     Ok(())
 }
 
-#[no_mangle]
-#[async_ffi(?Send)]
-#[allow(clippy::unnecessary_wraps)]
-#[allow(clippy::needless_pass_by_value)]
-async extern "Rust" fn chat(
-    mut chatbox: String,
-    mut console: String,
-    handle: Handle,
-) -> Result<(String, String)> {
-    let _enter = handle.enter();
+async fn chat(mut chatbox: String, mut console: String) -> Result<ChatMessage> {
     let config = config()?;
     let url = format!("http://ws.audioscrobbler.com/2.0/?method=user.getrecenttracks&user={}&api_key={}&format=json&limit=1", config.username, config.api_key);
     let response = ureq::get(&url).call()?;
@@ -1,24 +1,14 @@
 use anyhow::{bail, Context, Result};
-use async_ffi::async_ffi;
 use ferrispot::{
     model::{playback::PlayingType, track::FullTrack},
     prelude::*,
 };
+use loader::ChatMessage;
 use terminal_link::Link;
-use tokio::runtime::Handle;
 
 use crate::{LYRICS, SPOTIFY};
 
-#[no_mangle]
-#[async_ffi(?Send)]
-#[allow(clippy::unnecessary_wraps)]
-#[allow(clippy::needless_pass_by_value)]
-async extern "Rust" fn chat(
-    mut chatbox: String,
-    mut console: String,
-    handle: Handle,
-) -> Result<(String, String)> {
-    let _enter = handle.enter();
+pub async fn chat(mut chatbox: String, mut console: String) -> Result<ChatMessage> {
     let config = crate::config()?;
     let spotify = SPOTIFY.get().context("Spotify is Authenticating...")?;
     let mut lyrics = LYRICS.get().context("Lyrics is Authenticating...")?.clone();
@@ -1,4 +1,4 @@
-use std::{collections::HashMap, net::UdpSocket};
+use std::net::UdpSocket;
 
 use anyhow::Result;
 use ferrispot::{
@@ -6,24 +6,34 @@ use ferrispot::{
     model::playback::RepeatState,
     prelude::*,
 };
-use rosc::{decoder::MTU, OscMessage, OscPacket, OscType};
+use loader::{pattern::Pattern, sender::ParameterSender};
+use rosc::{decoder::MTU, OscPacket, OscType};
 
 #[allow(clippy::too_many_lines)]
 pub async fn start_loop(
     socket: UdpSocket,
     spotify: AsyncAuthorizationCodeUserClient,
 ) -> Result<()> {
-    let mut previous_parameters = HashMap::new();
+    let media = Pattern::new("/avatar/parameters/VRCOSC/Media/*")?;
     let mut muted_volume = None;
+    let mut sender = ParameterSender::new(&socket);
     let mut buf = [0u8; MTU];
     loop {
         let size = socket.recv(&mut buf)?;
+        if loader::plugin::unloading() {
+            return Ok(());
+        }
+
         let (_buf, packet) = rosc::decoder::decode_udp(&buf[..size])?;
         let OscPacket::Message(packet) = packet else {
-            continue; // I don't think VRChat uses bundles
+            continue; // The loader unpacks bundles
+        };
+
+        let Some(captures) = media.captures(&packet.addr) else {
+            continue; // Not a media parameter
         };
 
-        let addr = packet.addr.replace("/avatar/parameters/VRCOSC/Media/", "");
+        let addr = captures[0];
         let Some(arg) = packet.args.first() else {
             continue; // No first argument was supplied
         };
@@ -36,7 +46,7 @@ pub async fn start_loop(
             muted_volume = Some(playback_state.device().volume_percent());
         }
 
-        let request = match addr.as_ref() {
+        let request = match addr {
             "Play" => {
                 let OscType::Bool(play) = arg.to_owned() else {
                     continue;
@@ -111,46 +121,25 @@ pub async fn start_loop(
                     return Ok(()); // No media is currently playing
                 };
 
-                let mut parameters = HashMap::new();
-
                 let play = OscType::Bool(playback_state.device().is_active());
-                if previous_parameters.get("Play") != Some(&play) {
-                    parameters.insert("Play", play.clone());
-                    previous_parameters.insert("Play", play);
-                }
+                sender.set("VRCOSC/Media/Play", play)?;
 
                 let shuffle = OscType::Bool(playback_state.shuffle_state());
-                if previous_parameters.get("Shuffle") != Some(&shuffle) {
-                    parameters.insert("Shuffle", shuffle.clone());
-                    previous_parameters.insert("Shuffle", shuffle);
-                }
+                sender.set("VRCOSC/Media/Shuffle", shuffle)?;
 
                 let repeat = OscType::Int(match playback_state.repeat_state() {
                     RepeatState::Off => 0,
                     RepeatState::Track => 1,
                     RepeatState::Context => 2,
                 });
-                if previous_parameters.get("Repeat") != Some(&repeat) {
-                    parameters.insert("Repeat", repeat.clone());
-                    previous_parameters.insert("Repeat", repeat);
-                }
-
-                for (param, arg) in parameters {
-                    let packet = OscPacket::Message(OscMessage {
-                        addr: format!("/avatar/parameters/VRCOSC/Media/{param}"),
-                        args: vec![arg],
-                    });
-
-                    let msg_buf = rosc::encoder::encode(&packet)?;
-                    socket.send(&msg_buf)?;
-                }
+                sender.set("VRCOSC/Media/Repeat", repeat)?;
 
                 continue;
             }
         };
 
         if let Err(error) = request.send_async().await {
-            eprintln!("Spotify Control Error: {error}");
+            tracing::error!("Failed to control playback: {error}");
         };
     }
 }
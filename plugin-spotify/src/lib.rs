@@ -4,7 +4,7 @@
 mod chatbox;
 mod control;
 
-use std::{net::UdpSocket, sync::OnceLock, time::Duration};
+use std::{net::UdpSocket, sync::OnceLock};
 
 use anyhow::{bail, Result};
 use derive_config::DeriveTomlConfig;
@@ -79,7 +79,11 @@ static CONFIG: OnceLock<Config> = OnceLock::new();
 
 fn config() -> Result<&'static Config> {
     CONFIG.get_or_try_init(|| {
-        Config::load().or_else(|_| {
+        let config = Config::load().or_else(|_| {
+            if !loader::host::interactive() {
+                return Ok(Config::default()); // Left to the environment, nothing is saved
+            }
+
             println!("The Spotify plugin requires you to create a Spotify Developer Application");
             println!("https://github.com/ShayBox/VRC-OSC/tree/master/plugin-spotify#how-to-setup");
             println!("https://developer.spotify.com/dashboard");
@@ -109,15 +113,41 @@ fn config() -> Result<&'static Config> {
 
             config.save()?;
 
-            Ok(config)
-        })
+            Ok::<Config, anyhow::Error>(config)
+        })?;
+
+        let prefix = loader::env::plugin_prefix("Spotify");
+        let config = loader::env::apply(config, &prefix)?;
+        let missing = [
+            ("client", config.client.is_empty()),
+            ("secret", config.secret.is_empty() && !config.pkce),
+            ("redirect_uri", config.redirect_uri.is_empty()),
+        ]
+        .into_iter()
+        .filter_map(|(key, missing)| missing.then_some(key))
+        .collect::<Vec<_>>();
+
+        loader::env::require("The Spotify plugin", &prefix, &missing)?;
+
+        Ok(config)
     })
 }
 
-#[no_mangle]
+loader::export_plugin! {
+    name: "Spotify",
+    capabilities: Capabilities::CHAT
+        .union(Capabilities::OSC_CONSUMER)
+        .union(Capabilities::OSC_PRODUCER),
+    subscriptions: ["/avatar/parameters/VRCOSC/Media/*"],
+    config: Config,
+    load: load,
+    chat: chatbox::chat,
+    unload: unload,
+}
+
 #[allow(clippy::needless_pass_by_value)]
 #[tokio::main(flavor = "current_thread")]
-async extern "Rust" fn load(socket: UdpSocket) -> Result<()> {
+async fn load(socket: UdpSocket) -> Result<()> {
     let mut config = config()?.clone();
     let mut lyrics = SpotifyLyrics::from_browser(Browser::All)?;
     let spotify = login_to_spotify(&mut config).await?;
@@ -125,22 +155,50 @@ async extern "Rust" fn load(socket: UdpSocket) -> Result<()> {
     // Disable lyrics if Spotify Lyrics failed to authenticate
     if let Err(error) = lyrics.refresh_authorization().await {
         config.enable_lyrics = false;
-        eprintln!("{error}");
+        tracing::warn!("Disabling lyrics: {error}");
     };
 
-    SPOTIFY.set(spotify.clone()).expect("Failed to set SPOTIFY");
-    LYRICS.set(lyrics).expect("Failed to set LYRICS");
+    // Already set if the plugin was restarted
+    let _ = SPOTIFY.set(spotify.clone());
+    let _ = LYRICS.set(lyrics);
 
     if config.enable_control {
         control::start_loop(socket, spotify).await?;
     }
 
-    loop {
-        // Keep the threads alive - STATUS_ACCESS_VIOLATION
-        tokio::time::sleep(Duration::from_secs(u64::MAX)).await;
+    // Keep the chat provider logged in until the loader unloads the plugin
+    loader::plugin::unloaded().await;
+
+    Ok(())
+}
+
+/// Saves the refresh token, which Spotify can rotate while running
+fn unload() {
+    let Some(spotify) = SPOTIFY.get() else {
+        return; // Never logged in
+    };
+
+    if let Err(error) = save_refresh_token(spotify.get_refresh_token()) {
+        tracing::error!("Failed to save the refresh token: {error}");
     }
 }
 
+/// Only the refresh token, environment overrides shouldn't end up in the file
+fn save_refresh_token(refresh_token: String) -> Result<()> {
+    let mut saved = match Config::load() {
+        Ok(saved) => saved,
+        Err(_) if !loader::host::interactive() => return Ok(()), // Configured from the environment
+        Err(_) => Config::default(),
+    };
+
+    if saved.refresh_token != refresh_token {
+        saved.refresh_token = refresh_token;
+        saved.save()?;
+    }
+
+    Ok(())
+}
+
 async fn login_to_spotify(config: &mut Config) -> Result<AsyncAuthorizationCodeUserClient> {
     Ok(if config.pkce {
         let spotify_client = SpotifyClientBuilder::new(&config.client).build_async();
@@ -194,6 +252,11 @@ async fn finish_authentication_and_save(
     config: &mut Config,
     client: AsyncIncompleteAuthorizationCodeUserClient,
 ) -> Result<AsyncAuthorizationCodeUserClient> {
+    if !loader::host::interactive() {
+        let prefix = loader::env::plugin_prefix("Spotify");
+        loader::env::require("The Spotify plugin", &prefix, &["refresh_token"])?;
+    }
+
     let authorize_url = client.get_authorize_url();
     let redirect_uri = &config.redirect_uri;
 
@@ -203,7 +266,7 @@ async fn finish_authentication_and_save(
     spotify.refresh_access_token().await?;
 
     config.refresh_token = spotify.get_refresh_token();
-    config.save()?;
+    save_refresh_token(config.refresh_token.clone())?;
 
     Ok(spotify)
 }
@@ -211,7 +274,7 @@ async fn finish_authentication_and_save(
 fn get_user_authorization(authorize_url: &str, redirect_uri: &str) -> Result<(String, String)> {
     match webbrowser::open(authorize_url) {
         Ok(ok) => ok,
-        Err(why) => eprintln!(
+        Err(why) => tracing::warn!(
             "Error when trying to open an URL in your browser: {why:?}. \
              Please navigate here manually: {authorize_url}",
         ),
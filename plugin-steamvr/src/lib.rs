@@ -19,13 +19,23 @@ impl Default for Config {
     }
 }
 
-#[no_mangle]
+// SteamVR isn't available on macOS
+loader::export_plugin! {
+    name: "SteamVR",
+    platforms: Platforms::WINDOWS.union(Platforms::LINUX),
+    capabilities: Capabilities::NONE,
+    config: Config,
+    load: load,
+}
+
 #[allow(clippy::needless_pass_by_value)]
 #[tokio::main(flavor = "current_thread")]
-async extern "Rust" fn load(_socket: UdpSocket) -> Result<()> {
+async fn load(_socket: UdpSocket) -> Result<()> {
     if let Ok(context) = ovr_overlay::Context::init() {
         let manager = &mut context.applications_mngr();
-        let config = Config::load()?;
+        let config = Config::load().unwrap_or_default();
+        config.save()?;
+        let config = loader::env::apply(config, &loader::env::plugin_prefix("SteamVR"))?;
         let manifest = Manifest::load()?;
         let path = Manifest::get_path()?;
 